@@ -0,0 +1,543 @@
+//! Non-panicking deadlock checks.
+
+use crate::{
+    handle::{self, DeadlockHandle},
+    panics::WorkerPanic,
+    report::Split,
+    thread_state::ThreadState,
+    wait_for::{self, WaitCycle},
+    worker::{extend, Statement, Worker},
+    Config,
+};
+use std::{
+    backtrace::Backtrace,
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    sync::{Arc, Barrier},
+    thread::Thread,
+    time::{Duration, Instant},
+};
+
+/// Checks whether `stmt` deadlocks, without panicking.
+///
+/// `stmt` runs on a new thread. If it is still blocked `config` (usually a [`Duration`](`std::time::Duration`)) after it started,
+/// the thread is considered deadlocked.
+///
+/// This is the building block of [`assert_deadlock!`] and [`assert_no_deadlock!`],
+/// for custom assertions and retry logic.
+///
+/// # Errors
+///
+/// Iff `stmt` did not deadlock, see [`DeadlockCheckError`].
+///
+/// # Panics
+///
+/// Iff the worker thread can't be spawned, or the [leaked threads limit](`crate::set_leaked_threads_limit`) is reached.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{sync::{Arc, Mutex}, time::Duration};
+/// use assert_deadlock::{check_deadlock, DeadlockCheckError};
+///
+/// let mutex = Arc::new(Mutex::new(1));
+///
+/// match check_deadlock({
+///     let mutex = Arc::clone(&mutex);
+///     move || *mutex.lock().unwrap()
+/// }, Duration::from_secs(1)) {
+///     Err(DeadlockCheckError::Returned(1)) => (),
+///     _ => unreachable!(),
+/// }
+///
+/// let guard = mutex.lock().unwrap();
+/// assert!(check_deadlock({
+///     let mutex = Arc::clone(&mutex);
+///     move || drop(mutex.lock())
+/// }, Duration::from_secs(1)).is_ok());
+/// ```
+#[track_caller]
+pub fn check_deadlock<T: Send + 'static>(
+    stmt: impl FnOnce() -> T + Send + 'static,
+    config: impl Into<Config>,
+) -> Result<Deadlocked<T>, DeadlockCheckError<T>> {
+    check(Box::new(stmt), &config.into())
+}
+
+/// Like [`check_deadlock`], but `stmt` may borrow from the caller.
+///
+/// # Safety
+///
+/// Everything `stmt` borrows must stay valid for as long as the worker thread runs.
+///
+/// If `stmt` deadlocks, that's usually until the process exits,
+/// so this is only sound if the caller's borrowed locals are never deallocated before then.
+///
+/// Prefer [`check_deadlock`], which lets the thread own everything `stmt` captured (and leaks it along with the thread).
+///
+/// Unlike the lifetime, [`Send`] is still checked:
+///
+/// ```compile_fail
+/// # use std::{rc::Rc, time::Duration};
+/// use assert_deadlock::check_deadlock_unchecked;
+///
+/// let rc = Rc::new(());
+/// let _ = unsafe { check_deadlock_unchecked(|| drop(&rc), Duration::from_secs(1)) };
+/// ```
+///
+/// # Errors
+///
+/// Iff `stmt` did not deadlock, see [`DeadlockCheckError`].
+///
+/// # Panics
+///
+/// Like [`check_deadlock`].
+#[track_caller]
+pub unsafe fn check_deadlock_unchecked<'a, T: Send + 'static>(
+    stmt: impl FnOnce() -> T + Send + 'a,
+    config: impl Into<Config>,
+) -> Result<Deadlocked<T>, DeadlockCheckError<T>> {
+    let stmt: Box<dyn FnOnce() -> T + Send + 'a> = Box::new(stmt);
+    check(extend(stmt), &config.into())
+}
+
+/// Like [`check_deadlock`], but also returns what `stmt` wrote to its [`worker_output`](`crate::worker_output`) until the check ended.
+///
+/// [`check_deadlock`] discards that output, but it's often needed to tell why a check failed.
+/// Output written after the check can be taken from [`Deadlocked::take_output`].
+///
+/// # Panics
+///
+/// Like [`check_deadlock`].
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{io::Write, time::Duration};
+/// use assert_deadlock::{check_deadlock_captured, worker_output, DeadlockCheckError};
+///
+/// let (result, output) = check_deadlock_captured(
+///     || writeln!(worker_output(), "Not blocked!").unwrap(),
+///     Duration::from_secs(1),
+/// );
+/// assert!(matches!(result, Err(DeadlockCheckError::Returned(()))));
+/// assert_eq!(output, b"Not blocked!\n");
+/// ```
+#[track_caller]
+pub fn check_deadlock_captured<T: Send + 'static>(
+    stmt: impl FnOnce() -> T + Send + 'static,
+    config: impl Into<Config>,
+) -> (Result<Deadlocked<T>, DeadlockCheckError<T>>, Vec<u8>) {
+    check_captured(Box::new(stmt), &config.into())
+}
+
+/// Like [`check_deadlock_unchecked`], but also returns what `stmt` wrote to its [`worker_output`](`crate::worker_output`) until the check ended.
+///
+/// See [`check_deadlock_captured`].
+///
+/// # Safety
+///
+/// See [`check_deadlock_unchecked`].
+///
+/// # Panics
+///
+/// Like [`check_deadlock`].
+#[track_caller]
+pub unsafe fn check_deadlock_unchecked_captured<'a, T: Send + 'static>(
+    stmt: impl FnOnce() -> T + Send + 'a,
+    config: impl Into<Config>,
+) -> (Result<Deadlocked<T>, DeadlockCheckError<T>>, Vec<u8>) {
+    let stmt: Box<dyn FnOnce() -> T + Send + 'a> = Box::new(stmt);
+    check_captured(extend(stmt), &config.into())
+}
+
+/// Runs `stmt` on a [`Worker`] and observes it as configured.
+#[track_caller]
+fn check<T: Send + 'static>(
+    stmt: Statement<T>,
+    config: &Config,
+) -> Result<Deadlocked<T>, DeadlockCheckError<T>> {
+    check_captured(stmt, config).0
+}
+
+/// Like [`check`], but also returns the statement's output so far.
+#[track_caller]
+fn check_captured<T: Send + 'static>(
+    stmt: Statement<T>,
+    config: &Config,
+) -> Split<Deadlocked<T>, T> {
+    let worker = Worker::spawn(stmt, config);
+    let output = worker.output().clone();
+    let result = match worker.start(config.start_timeout()) {
+        Some(started) => observe(worker, started, started + config.duration(), config),
+        None => Err(DeadlockCheckError::NotStarted),
+    };
+    (result, output.take())
+}
+
+/// Checks whether each of `stmts` deadlocks, without panicking.
+///
+/// Each statement runs on its own worker thread.
+/// With a [start barrier](`Config::with_start_barrier`), they all start running only once every worker thread is ready.
+/// If any of them doesn't start within the [start timeout](`Config::with_start_timeout`), the others never pass the barrier,
+/// so then all of them are reported as [`NotStarted`](`DeadlockCheckError::NotStarted`).
+/// Every statement is then observed until `config`'s duration passed since the last one started,
+/// which makes this suitable for deadlocks that involve several threads, like two of them locking two mutexes in opposite order.
+///
+/// This is the building block of [`assert_deadlock_all!`](`crate::assert_deadlock_all!`).
+///
+/// Returns a result for each statement, in order, like [`check_deadlock`] would.
+///
+/// # Panics
+///
+/// Iff a worker thread can't be spawned, or the [leaked threads limit](`crate::set_leaked_threads_limit`) is reached before all of them are.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{sync::{Arc, Mutex}, thread, time::Duration};
+/// use assert_deadlock::{check_deadlock_all, Config};
+///
+/// let a = Arc::new(Mutex::new(()));
+/// let b = Arc::new(Mutex::new(()));
+///
+/// let lock_both = |first: &Arc<Mutex<()>>, second: &Arc<Mutex<()>>| {
+///     let (first, second) = (Arc::clone(first), Arc::clone(second));
+///     Box::new(move || {
+///         let _first = first.lock().unwrap();
+///         thread::sleep(Duration::from_millis(100));
+///         drop(second.lock().unwrap());
+///     }) as Box<dyn FnOnce() + Send>
+/// };
+///
+/// let line = line!() + 1;
+/// let results = check_deadlock_all(
+///     vec![lock_both(&a, &b), lock_both(&b, &a)],
+///     Config::new(Duration::from_secs(1)).with_start_barrier(true),
+/// );
+/// assert!(results.iter().all(Result::is_ok));
+///
+/// // The workers are named after the call site.
+/// let name = format!("assert_deadlock@{}:{}", file!(), line);
+/// assert_eq!(results[0].as_ref().unwrap().thread().name(), Some(name.as_str()));
+/// ```
+#[track_caller]
+pub fn check_deadlock_all<T: Send + 'static>(
+    stmts: impl IntoIterator<Item = Box<dyn FnOnce() -> T + Send + 'static>>,
+    config: impl Into<Config>,
+) -> Vec<Result<Deadlocked<T>, DeadlockCheckError<T>>> {
+    check_deadlock_all_captured(stmts, config)
+        .into_iter()
+        .map(|(result, _)| result)
+        .collect()
+}
+
+/// Like [`check_deadlock_all`], but also returns what each statement wrote to its [`worker_output`](`crate::worker_output`) until the check ended.
+///
+/// This is used by [`assert_deadlock_all!`](`crate::assert_deadlock_all!`).
+#[doc(hidden)]
+#[track_caller]
+pub fn check_deadlock_all_captured<T: Send + 'static>(
+    stmts: impl IntoIterator<Item = Box<dyn FnOnce() -> T + Send + 'static>>,
+    config: impl Into<Config>,
+) -> Vec<Split<Deadlocked<T>, T>> {
+    let config = config.into();
+    let stmts: Vec<_> = stmts.into_iter().collect();
+    let barrier = config
+        .start_barrier()
+        .then(|| Arc::new(Barrier::new(stmts.len())));
+    // Not a closure, so that `#[track_caller]` names the workers after the caller.
+    let mut workers = Vec::with_capacity(stmts.len());
+    for stmt in stmts {
+        let stmt: Statement<T> = match &barrier {
+            Some(barrier) => {
+                let barrier = Arc::clone(barrier);
+                Box::new(move || {
+                    barrier.wait();
+                    stmt()
+                })
+            }
+            None => stmt,
+        };
+        workers.push(Worker::spawn(stmt, &config));
+    }
+    let started: Vec<_> = workers
+        .iter()
+        .map(|worker| worker.start(config.start_timeout()))
+        .collect();
+    if barrier.is_some() && started.iter().any(Option::is_none) {
+        // The others are stuck at the barrier rather than in their statements.
+        return workers
+            .iter()
+            .map(|worker| (Err(DeadlockCheckError::NotStarted), worker.output().take()))
+            .collect();
+    }
+    let deadline = started
+        .iter()
+        .flatten()
+        .max()
+        .copied()
+        .unwrap_or_else(Instant::now)
+        + config.duration();
+    workers
+        .into_iter()
+        .zip(started)
+        .map(|(worker, started)| {
+            let output = worker.output().clone();
+            let result = match started {
+                Some(started) => observe(worker, started, deadline, &config),
+                None => Err(DeadlockCheckError::NotStarted),
+            };
+            (result, output.take())
+        })
+        .collect()
+}
+
+/// Observes `worker`'s statement, which started at `started`, until `deadline` and then verifies it's blocked as configured.
+///
+/// Succeeds early if the worker is stuck in a cycle of [tracked locks](`crate::sync`).
+fn observe<T>(
+    worker: Worker<T>,
+    started: Instant,
+    mut deadline: Instant,
+    config: &Config,
+) -> Result<Deadlocked<T>, DeadlockCheckError<T>> {
+    let mut early_success = config.early_success();
+    let mut previous: Option<ThreadState> = None;
+    let mut same = 0;
+    while Instant::now() < deadline {
+        let interval = early_success.map_or(CYCLE_INTERVAL, |(_, interval)| interval);
+        if let Some(result) = worker.wait_until(deadline.min(Instant::now() + interval)) {
+            return Err(completed(result));
+        }
+        if wait_for::is_active() {
+            if let Some(cycle) = wait_for::find_cycle(worker.thread().id()) {
+                let thread_state = worker.sample();
+                return Ok(Deadlocked {
+                    worker,
+                    started,
+                    thread_state,
+                    cycle: Some(cycle),
+                });
+            }
+        }
+        let Some((samples, _)) = early_success else {
+            continue;
+        };
+        let Some(state) = worker.sample() else {
+            // Not supported, so wait out the full duration.
+            early_success = None;
+            continue;
+        };
+        if !state.is_blocked() {
+            // Busy (or not in a system call), so this doesn't count towards early success.
+            same = 0;
+        } else if previous
+            .as_ref()
+            .is_some_and(|previous| previous.is_same_wait(&state))
+        {
+            same += 1;
+        } else {
+            same = 0;
+        }
+        if state.is_blocked() && same + 1 >= samples {
+            return Ok(Deadlocked {
+                worker,
+                started,
+                thread_state: Some(state),
+                cycle: None,
+            });
+        }
+        previous = Some(state);
+    }
+
+    let mut thread_state = None;
+    for _ in 0..VERIFICATION_SAMPLES {
+        if let Some(result) = worker.wait_until(deadline) {
+            return Err(completed(result));
+        }
+        if !config.verify_blocked() {
+            break;
+        }
+        match worker.sample() {
+            // Not supported, or the thread exited just now (which the next iteration picks up).
+            None => (),
+            Some(state) if state.is_blocked() => thread_state = Some(state),
+            Some(state) => {
+                // Maybe it finished in the meantime.
+                return Err(worker.wait_until(Instant::now()).map_or_else(
+                    || DeadlockCheckError::NotBlocked(Box::new(state), worker.backtrace()),
+                    completed,
+                ));
+            }
+        }
+        deadline = Instant::now() + VERIFICATION_INTERVAL;
+    }
+    Ok(Deadlocked {
+        worker,
+        started,
+        thread_state,
+        cycle: None,
+    })
+}
+
+/// Maps the result of a statement that completed to the matching [`DeadlockCheckError`].
+fn completed<T>(result: Result<T, WorkerPanic>) -> DeadlockCheckError<T> {
+    match result {
+        Ok(value) => DeadlockCheckError::Returned(value),
+        Err(panic) => DeadlockCheckError::Panicked(panic),
+    }
+}
+
+/// How often the worker thread's state is sampled to [verify that it's blocked](`Config::with_verify_blocked`).
+pub(crate) const VERIFICATION_SAMPLES: usize = 3;
+
+/// The interval between [`VERIFICATION_SAMPLES`].
+pub(crate) const VERIFICATION_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How often the [wait-for graph](`crate::sync`) is searched for a cycle, unless [early success](`Config::with_early_success`) samples more often.
+const CYCLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// A statement that deadlocked, as found by [`check_deadlock`].
+///
+/// Dropping this abandons the blocked thread.
+pub struct Deadlocked<T> {
+    /// The still-running worker.
+    worker: Worker<T>,
+    /// When the statement started running.
+    started: Instant,
+    /// The last verified blocked state.
+    thread_state: Option<ThreadState>,
+    /// The cycle of tracked locks that proves the deadlock, if any.
+    cycle: Option<WaitCycle>,
+}
+
+impl<T> Deadlocked<T> {
+    /// The last [`ThreadState`] sampled to verify that the thread is blocked.
+    ///
+    /// This is [`None`] unless [verification](`Config::with_verify_blocked`) is enabled and supported on this platform.
+    #[must_use]
+    pub fn thread_state(&self) -> Option<&ThreadState> {
+        self.thread_state.as_ref()
+    }
+
+    /// The cycle of threads waiting on each other's [tracked locks](`crate::sync`) that the statement is stuck in, if one was found.
+    ///
+    /// Unlike a merely blocked [`thread_state`](`Deadlocked::thread_state`), this proves the deadlock.
+    #[must_use]
+    pub fn cycle(&self) -> Option<&WaitCycle> {
+        self.cycle.as_ref()
+    }
+
+    /// When the statement started running (and, as far as this crate can tell, blocking).
+    #[must_use]
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// The deadlocked thread.
+    #[must_use]
+    pub fn thread(&self) -> &Thread {
+        self.worker.thread()
+    }
+
+    /// Captures where the deadlocked thread is stuck right now.
+    ///
+    /// On Linux, this interrupts the thread with the real-time signal `SIGRTMIN`,
+    /// whose handler (installed on first use) records a [`Backtrace`].
+    /// Don't use `SIGRTMIN` elsewhere in tests that call this.
+    ///
+    /// Locks (including [`std::sync`]'s) and [`thread::sleep`](`std::thread::sleep`) retry after the interruption,
+    /// so this doesn't resolve a deadlock on them.
+    /// Other blocking calls may return early, though:
+    /// `poll`, `epoll_wait`, `nanosleep`, `pause` and raw futex waits fail with `EINTR`, and condition variable waits can wake up spuriously.
+    /// A statement that doesn't handle that may then behave differently.
+    ///
+    /// The handler allocates and takes the standard library's backtrace lock, which is unsound in general inside a signal handler.
+    /// As a best effort, the thread is only interrupted if it's [blocked](`ThreadState::is_blocked`) right before,
+    /// which rules out a busy thread, but not one that sleeps on a futex inside libc or `std` while holding such a lock.
+    /// In that rare case, the thread (and this call, for up to a second) may hang.
+    ///
+    /// Returns [`None`] on other platforms, if the thread isn't blocked,
+    /// or if it doesn't respond within a second, e.g. because it's in uninterruptible sleep.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use std::{sync::Mutex, time::Duration};
+    /// use assert_deadlock::check_deadlock;
+    ///
+    /// static MUTEX: Mutex<()> = Mutex::new(());
+    ///
+    /// let guard = MUTEX.lock();
+    /// let deadlocked = check_deadlock(|| drop(MUTEX.lock()), Duration::from_millis(100)).unwrap();
+    /// if cfg!(target_os = "linux") {
+    ///     let backtrace = deadlocked.backtrace().unwrap().to_string();
+    ///     assert!(backtrace.contains("Mutex"));
+    /// }
+    /// ```
+    #[must_use]
+    pub fn backtrace(&self) -> Option<Backtrace> {
+        self.worker.backtrace()
+    }
+
+    /// Takes what the statement wrote to its [`worker_output`](`crate::worker_output`) since the check ended (or this was last called).
+    ///
+    /// Output from before that is returned by [`check_deadlock_captured`] instead.
+    #[must_use]
+    pub fn take_output(&self) -> Vec<u8> {
+        self.worker.output().take()
+    }
+
+    /// Converts this into a [`DeadlockHandle`], which can later join the worker thread.
+    #[must_use]
+    pub fn into_handle(self) -> DeadlockHandle<T> {
+        handle::new(self.worker, self.started, self.cycle)
+    }
+}
+
+impl<T> Debug for Deadlocked<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Deadlocked")
+            .field("thread", self.thread())
+            .field("started", &self.started)
+            .field("thread_state", &self.thread_state)
+            .field("cycle", &self.cycle)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why [`check_deadlock`] found no deadlock.
+#[derive(Debug)]
+pub enum DeadlockCheckError<T> {
+    /// The statement returned this value in time.
+    Returned(T),
+    /// The statement panicked.
+    Panicked(WorkerPanic),
+    /// The worker thread did not start running the statement within the [start timeout](`Config::with_start_timeout`).
+    NotStarted,
+    /// The statement was still running after the duration, but [not blocked](`Config::with_verify_blocked`).
+    ///
+    /// This usually means that it's busy computing (or livelocked) rather than deadlocked.
+    /// The thread is abandoned.
+    ///
+    /// The [`Backtrace`] shows where the thread was stuck, if it could be captured.
+    /// That's only the case if it happened to be blocked again by then (see [`Deadlocked::backtrace`]),
+    /// as a running thread isn't interrupted.
+    NotBlocked(Box<ThreadState>, Option<Backtrace>),
+}
+
+impl<T> Display for DeadlockCheckError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DeadlockCheckError::Returned(_) => write!(f, "The statement returned."),
+            DeadlockCheckError::Panicked(_) => write!(f, "The statement panicked."),
+            DeadlockCheckError::NotStarted => write!(f, "The statement did not start in time."),
+            DeadlockCheckError::NotBlocked(state, _) => {
+                write!(f, "The statement was still running, not blocked: {state}")
+            }
+        }
+    }
+}
+
+impl<T: Debug> Error for DeadlockCheckError<T> {}
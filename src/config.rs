@@ -0,0 +1,295 @@
+//! Per-assertion settings.
+
+use std::time::Duration;
+
+/// Settings for a deadlock assertion.
+///
+/// Anywhere this crate's macros take a `$duration`, they accept a [`Config`] too.
+/// A plain [`Duration`] converts into a [`Config`] with default settings.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{sync::Mutex, time::Duration};
+/// use assert_deadlock::{assert_deadlock, Config};
+///
+/// static MUTEX: Mutex<()> = Mutex::new(());
+///
+/// let guard = MUTEX.lock();
+/// assert_deadlock!(
+///     { Box::leak(Box::new(MUTEX.lock())); },
+///     Config::new(Duration::from_secs(1)).with_start_timeout(Duration::from_secs(30)),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct Config {
+    /// How long the statement is observed once it started.
+    duration: Duration,
+    /// How long the worker thread may take to start the statement.
+    start_timeout: Duration,
+    /// Whether to check that the worker is asleep in the kernel before accepting a deadlock.
+    verify_blocked: bool,
+    /// After how many identical blocked samples, taken how far apart, to accept a deadlock early.
+    early_success: Option<(u32, Duration)>,
+    /// The worker thread's name, instead of one derived from the call site.
+    thread_name: Option<String>,
+    /// The worker thread's stack size, instead of the default.
+    stack_size: Option<usize>,
+    /// Whether multiple statements start running together.
+    start_barrier: bool,
+}
+
+impl Config {
+    /// The default [`start_timeout`](`Config::with_start_timeout`).
+    pub const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Creates a new [`Config`] that observes the statement for `duration`.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            start_timeout: Self::DEFAULT_START_TIMEOUT,
+            verify_blocked: true,
+            early_success: None,
+            thread_name: None,
+            stack_size: None,
+            start_barrier: false,
+        }
+    }
+
+    /// Sets how long the worker thread may take to start running the statement.
+    ///
+    /// This grace period is separate from and precedes the observation [`duration`](`Config::duration`),
+    /// so that a heavily loaded machine doesn't cause spurious failures.
+    ///
+    /// Defaults to [`DEFAULT_START_TIMEOUT`](`Config::DEFAULT_START_TIMEOUT`).
+    pub fn with_start_timeout(mut self, start_timeout: Duration) -> Self {
+        self.start_timeout = start_timeout;
+        self
+    }
+
+    /// Sets whether to verify that the worker thread is actually blocked, rather than just slow, before accepting a deadlock.
+    ///
+    /// On Linux, this samples the thread's [`ThreadState`](`crate::ThreadState`) a few times once `duration` passed,
+    /// and only accepts it as deadlocked if it's asleep in a system call (like `futex`) each time.
+    /// Otherwise, the check fails with [`DeadlockCheckError::NotBlocked`](`crate::DeadlockCheckError::NotBlocked`).
+    ///
+    /// This has no effect on other platforms.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use std::time::Duration;
+    /// use assert_deadlock::{check_deadlock, Config, DeadlockCheckError};
+    ///
+    /// let busy = || loop {
+    ///     std::hint::spin_loop();
+    /// };
+    ///
+    /// let result = check_deadlock(busy, Duration::from_millis(100));
+    /// if cfg!(target_os = "linux") {
+    ///     assert!(matches!(result, Err(DeadlockCheckError::NotBlocked(..))));
+    /// }
+    ///
+    /// let config = Config::new(Duration::from_millis(100)).with_verify_blocked(false);
+    /// assert!(check_deadlock(busy, config).is_ok());
+    /// ```
+    pub fn with_verify_blocked(mut self, verify_blocked: bool) -> Self {
+        self.verify_blocked = verify_blocked;
+        self
+    }
+
+    /// Enables adaptive early success:
+    /// The worker thread's [`ThreadState`](`crate::ThreadState`) is sampled every `interval`,
+    /// and once `samples` consecutive samples show it [in the same wait without using CPU time](`crate::ThreadState::is_same_wait`),
+    /// the statement is considered deadlocked without waiting out the rest of [`duration`](`Config::duration`).
+    ///
+    /// The full duration remains the upper bound, e.g. on platforms where the thread state isn't available.
+    ///
+    /// This only applies to assertions that expect a deadlock.
+    /// [`assert_no_deadlock!`](`crate::assert_no_deadlock!`) and [`assert_blocks_for!`](`crate::assert_blocks_for!`) ignore it,
+    /// since a parked statement may still return, e.g. from a timed wait.
+    ///
+    /// `samples` is clamped to at least `2`, since a single sample can't show that the thread stays in the same wait.
+    /// Samples of a thread that isn't [blocked](`crate::ThreadState::is_blocked`) never count, so a busy statement still runs for the full duration
+    /// (and then fails [verification](`Config::with_verify_blocked`)).
+    ///
+    /// Disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use std::{sync::Mutex, time::{Duration, Instant}};
+    /// use assert_deadlock::{assert_deadlock, Config};
+    ///
+    /// static MUTEX: Mutex<()> = Mutex::new(());
+    ///
+    /// let guard = MUTEX.lock();
+    /// let start = Instant::now();
+    /// assert_deadlock!(
+    ///     forget; MUTEX.lock(),
+    ///     Config::new(Duration::from_secs(10)).with_early_success(5, Duration::from_millis(10)),
+    /// );
+    /// if cfg!(target_os = "linux") {
+    ///     assert!(start.elapsed() < Duration::from_secs(10));
+    /// }
+    /// ```
+    ///
+    /// A busy statement isn't accepted early:
+    ///
+    /// ```rust
+    /// # use std::{hint::spin_loop, time::{Duration, Instant}};
+    /// use assert_deadlock::{check_deadlock, Config};
+    ///
+    /// let start = Instant::now();
+    /// let result = check_deadlock(
+    ///     || loop {
+    ///         spin_loop();
+    ///     },
+    ///     Config::new(Duration::from_millis(500)).with_early_success(1, Duration::from_millis(10)),
+    /// );
+    /// assert!(start.elapsed() >= Duration::from_millis(500));
+    /// if cfg!(target_os = "linux") {
+    ///     assert!(result.is_err());
+    /// }
+    /// ```
+    ///
+    /// Nor is a timed wait in an assertion that expects it to complete:
+    ///
+    /// ```rust
+    /// # use std::{sync::mpsc, time::Duration};
+    /// use assert_deadlock::{assert_blocks_for, assert_no_deadlock, Config};
+    ///
+    /// let config = Config::new(Duration::from_secs(2)).with_early_success(3, Duration::from_millis(10));
+    ///
+    /// let (_sender, receiver) = mpsc::channel::<()>();
+    /// let result = assert_no_deadlock!(
+    ///     receiver.recv_timeout(Duration::from_millis(500)),
+    ///     config.clone(),
+    /// );
+    /// assert_eq!(result, Err(mpsc::RecvTimeoutError::Timeout));
+    ///
+    /// let (_sender, receiver) = mpsc::channel::<()>();
+    /// let result = assert_blocks_for!(
+    ///     receiver.recv_timeout(Duration::from_millis(500)),
+    ///     Duration::from_millis(100),
+    ///     config,
+    /// );
+    /// assert_eq!(result, Err(mpsc::RecvTimeoutError::Timeout));
+    /// ```
+    pub fn with_early_success(mut self, samples: u32, interval: Duration) -> Self {
+        self.early_success = Some((samples.max(2), interval));
+        self
+    }
+
+    /// Sets the name of the worker thread.
+    ///
+    /// By default, it's named after the call site of the assertion or check, like `assert_deadlock@src/lib.rs:42`,
+    /// which shows up in panic messages, debuggers and tools like `top -H`.
+    ///
+    /// Linux truncates thread names to 15 bytes at the kernel level, but the full name is used within Rust.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use std::{sync::Mutex, time::Duration};
+    /// use assert_deadlock::{assert_deadlock, Config};
+    ///
+    /// static MUTEX: Mutex<()> = Mutex::new(());
+    ///
+    /// let guard = MUTEX.lock();
+    /// let handle = assert_deadlock!(forget; MUTEX.lock(), Duration::from_millis(100));
+    /// assert_eq!(handle.thread().name(), Some(&*format!("assert_deadlock@src/config.rs:{}", line!() - 1)));
+    ///
+    /// let handle = assert_deadlock!(
+    ///     forget; MUTEX.lock(),
+    ///     Config::new(Duration::from_millis(100)).with_thread_name("blocked on MUTEX"),
+    /// );
+    /// assert_eq!(handle.thread().name(), Some("blocked on MUTEX"));
+    /// ```
+    pub fn with_thread_name(mut self, thread_name: impl Into<String>) -> Self {
+        self.thread_name = Some(thread_name.into());
+        self
+    }
+
+    /// Sets the stack size of the worker thread, in bytes.
+    ///
+    /// Defaults to that of [`thread::Builder`](`std::thread::Builder::stack_size`),
+    /// which respects the `RUST_MIN_STACK` environment variable.
+    pub fn with_stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Sets whether [multiple statements](`crate::assert_deadlock_all!`) wait for each other's worker threads to be ready,
+    /// so that they start running at the same time.
+    ///
+    /// This makes interleavings like "each thread holds its first lock" more likely, but doesn't guarantee them.
+    ///
+    /// This has no effect on single-statement assertions.
+    ///
+    /// Defaults to `false`.
+    pub fn with_start_barrier(mut self, start_barrier: bool) -> Self {
+        self.start_barrier = start_barrier;
+        self
+    }
+
+    /// How long the statement is observed once it started.
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// How long the worker thread may take to start running the statement.
+    #[must_use]
+    pub fn start_timeout(&self) -> Duration {
+        self.start_timeout
+    }
+
+    /// Whether to verify that the worker thread is actually blocked.
+    #[must_use]
+    pub fn verify_blocked(&self) -> bool {
+        self.verify_blocked
+    }
+
+    /// After how many consecutive identical blocked samples, taken how far apart, a deadlock is accepted early.
+    #[must_use]
+    pub fn early_success(&self) -> Option<(u32, Duration)> {
+        self.early_success
+    }
+
+    /// The configured name of the worker thread, if any.
+    #[must_use]
+    pub fn thread_name(&self) -> Option<&str> {
+        self.thread_name.as_deref()
+    }
+
+    /// The configured stack size of the worker thread, if any.
+    #[must_use]
+    pub fn stack_size(&self) -> Option<usize> {
+        self.stack_size
+    }
+
+    /// Whether multiple statements start running together.
+    #[must_use]
+    pub fn start_barrier(&self) -> bool {
+        self.start_barrier
+    }
+}
+
+/// Disables [early success](`Config::with_early_success`) for assertions that expect the statement to complete.
+///
+/// A statement that's stably parked may still return, e.g. from a timed wait.
+#[doc(hidden)]
+pub fn expecting_completion(mut config: Config) -> Config {
+    config.early_success = None;
+    config
+}
+
+impl From<Duration> for Config {
+    fn from(duration: Duration) -> Self {
+        Self::new(duration)
+    }
+}
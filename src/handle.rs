@@ -0,0 +1,150 @@
+//! Control over a deadlocked statement after the fact.
+
+use crate::{
+    check::Deadlocked, panics::WorkerPanic, report::WorkerStack, wait_for::WaitCycle,
+    worker::Worker,
+};
+use std::{
+    backtrace::Backtrace,
+    fmt::{self, Debug, Formatter},
+    thread::Thread,
+    time::{Duration, Instant},
+};
+
+/// A handle to a deadlocked statement's worker thread, which owns its result slot.
+///
+/// This is returned by [`assert_deadlock!`] and [`Deadlocked::into_handle`],
+/// so that a test can lift the blocking condition and then check what happens.
+///
+/// Dropping this abandons the blocked thread.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{sync::Mutex, time::Duration};
+/// use assert_deadlock::assert_deadlock;
+///
+/// static MUTEX: Mutex<i32> = Mutex::new(1);
+///
+/// let guard = MUTEX.lock().unwrap();
+/// let handle = assert_deadlock!(
+///     *MUTEX.lock().unwrap() += 1,
+///     Duration::from_secs(1),
+/// );
+///
+/// drop(guard);
+/// handle.join_within(Duration::from_secs(1));
+/// assert_eq!(*MUTEX.lock().unwrap(), 2);
+/// ```
+pub struct DeadlockHandle<T> {
+    /// The (so far) blocked worker.
+    worker: Worker<T>,
+    /// When the statement started running.
+    started: Instant,
+    /// The cycle of tracked locks that proved the deadlock, if any.
+    cycle: Option<WaitCycle>,
+}
+
+impl<T> DeadlockHandle<T> {
+    /// When the statement started running.
+    #[must_use]
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// The cycle of [tracked locks](`crate::sync`) that proved the deadlock, if one was found.
+    ///
+    /// See [`Deadlocked::cycle`].
+    #[must_use]
+    pub fn cycle(&self) -> Option<&WaitCycle> {
+        self.cycle.as_ref()
+    }
+
+    /// The worker thread.
+    #[must_use]
+    pub fn thread(&self) -> &Thread {
+        self.worker.thread()
+    }
+
+    /// Captures where the worker thread is stuck right now.
+    ///
+    /// See [`Deadlocked::backtrace`] for how this works and its caveats.
+    #[must_use]
+    pub fn backtrace(&self) -> Option<Backtrace> {
+        self.worker.backtrace()
+    }
+
+    /// Takes what the statement wrote to its [`worker_output`](`crate::worker_output`) since its check ended (or this was last called).
+    ///
+    /// See [`Deadlocked::take_output`].
+    #[must_use]
+    pub fn take_output(&self) -> Vec<u8> {
+        self.worker.output().take()
+    }
+
+    /// Asserts that the statement completes within `duration` and joins its thread.
+    ///
+    /// Returns the statement's value.
+    ///
+    /// # Panics
+    ///
+    /// Iff the statement is still blocked after `duration`.
+    ///
+    /// If the statement panics, that panic is propagated.
+    #[must_use = "The statement's value is dropped here unless used."]
+    #[track_caller]
+    pub fn join_within(self, duration: Duration) -> T {
+        let result = self.try_join_within(duration).unwrap_or_else(|handle| {
+            panic!(
+                "DeadlockHandle::join_within: Statement still blocked after {:?}{}",
+                duration,
+                WorkerStack(handle.backtrace().as_ref()),
+            )
+        });
+        result.unwrap_or_else(|panic| panic.resume())
+    }
+
+    /// Waits for up to `duration` for the statement to complete, then joins its thread.
+    ///
+    /// # Errors
+    ///
+    /// Iff the statement is still blocked after `duration`, in which case the handle is returned unchanged.
+    pub fn try_join_within(self, duration: Duration) -> Result<Result<T, WorkerPanic>, Self> {
+        match self.worker.wait_until(Instant::now() + duration) {
+            Some(result) => {
+                self.worker.join();
+                Ok(result)
+            }
+            None => Err(self),
+        }
+    }
+}
+
+impl<T> From<Deadlocked<T>> for DeadlockHandle<T> {
+    fn from(deadlocked: Deadlocked<T>) -> Self {
+        deadlocked.into_handle()
+    }
+}
+
+impl<T> Debug for DeadlockHandle<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadlockHandle")
+            .field("thread", self.thread())
+            .field("started", &self.started)
+            .field("cycle", &self.cycle)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Creates a [`DeadlockHandle`] from its parts. Used by [`Deadlocked::into_handle`].
+pub(crate) fn new<T>(
+    worker: Worker<T>,
+    started: Instant,
+    cycle: Option<WaitCycle>,
+) -> DeadlockHandle<T> {
+    DeadlockHandle {
+        worker,
+        started,
+        cycle,
+    }
+}
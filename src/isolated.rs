@@ -0,0 +1,462 @@
+//! Deadlock checks in a forked child process, on Linux.
+
+use crate::{
+    check::{VERIFICATION_INTERVAL, VERIFICATION_SAMPLES},
+    linux,
+    panics::{self, WorkerPanic},
+    report::{CheckResult, ProvenCycle, Split},
+    sync::WaitCycle,
+    thread_state::ThreadState,
+    Config, DeadlockCheckError,
+};
+use std::{
+    convert::{TryFrom, TryInto},
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    ptr, thread,
+    time::Instant,
+};
+
+/// Checks whether `stmt` deadlocks, without panicking, by running it in a forked child process.
+///
+/// The child is a copy of the calling process with only the calling thread,
+/// so `stmt` may borrow freely and doesn't have to be [`Send`].
+/// It reports back over a pipe when `stmt` starts and whether it returned or panicked.
+/// Once the check is done, the child is killed with `SIGKILL` and reaped,
+/// so a deadlocked `stmt` leaves no stuck thread, locks or memory behind in the test process.
+///
+/// Effects of `stmt` happen only in the child and aren't observable by the caller,
+/// and its value is dropped there.
+/// On the other hand, `stmt` can lock up on process-global locks without affecting later tests.
+///
+/// Locks that *other* threads held at the time of the fork stay locked forever in the child.
+/// If `stmt` uses such a lock (this includes [standard output](`std::io::stdout`) while another thread prints),
+/// it deadlocks where it otherwise wouldn't.
+///
+/// A panic in the child is reported with its message, thread name and location, but without payload type or backtrace.
+/// If the child crashes instead (e.g. on [abort](`std::process::abort`)), that's reported as panic too.
+///
+/// Everything the child writes to its standard output and error file descriptors is captured,
+/// and available through [`IsolatedDeadlock::output`] or [`IsolatedCheckError::output`].
+/// [`assert_deadlock!`](`crate::assert_deadlock!`) includes it in failure reports and discards it on success.
+/// Note that the test harness captures [`print!`] and [`eprint!`] (unless run with `--nocapture`)
+/// in a buffer that the child can't pass back, so only direct writes (including by C code and child processes) are seen in that case.
+/// An incomplete last line may be lost in the [`stdout`](`std::io::stdout`) buffer.
+///
+/// On Linux, the child's main thread is [verified to be blocked](`Config::with_verify_blocked`) as usual,
+/// but [early success](`Config::with_early_success`) isn't supported.
+///
+/// # Safety
+///
+/// No other thread may hold a lock at the time of the fork that the child then takes,
+/// e.g. the allocator's, the panic hook's, [standard output](`std::io::stdout`)'s or the environment's.
+/// Besides `stmt` itself, the child allocates and catches panics, so an async-signal-safe `stmt` isn't enough.
+///
+/// The simplest way to uphold this is to only call this while the process is single-threaded, as in a doctest.
+/// The test harness usually runs tests on several threads, though.
+///
+/// Otherwise, the child may hang after it reported that `stmt` started,
+/// so that this reports a deadlock that `stmt` doesn't actually have.
+///
+/// # Errors
+///
+/// Iff `stmt` did not deadlock, see [`IsolatedCheckError`].
+/// [`DeadlockCheckError::NotBlocked`] never includes a [`Backtrace`](`std::backtrace::Backtrace`) here.
+///
+/// # Panics
+///
+/// Iff the pipe, output file or child process can't be created.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{sync::Mutex, time::Duration};
+/// use assert_deadlock::{check_deadlock_isolated, DeadlockCheckError};
+///
+/// let mutex = Mutex::new(1);
+///
+/// //SAFETY: Doctests run single-threaded.
+/// unsafe {
+///     let guard = mutex.lock().unwrap();
+///     assert!(check_deadlock_isolated(|| mutex.lock(), Duration::from_secs(1)).is_ok());
+///     drop(guard);
+///
+///     assert!(matches!(
+///         check_deadlock_isolated(|| mutex.lock(), Duration::from_secs(1)).map_err(|e| e.into_error()),
+///         Err(DeadlockCheckError::Returned(())),
+///     ));
+///
+///     match check_deadlock_isolated::<()>(|| panic!("Inner panic!"), Duration::from_secs(1)).map_err(|e| e.into_error()) {
+///         Err(DeadlockCheckError::Panicked(panic)) => {
+///             assert_eq!(panic.message(), Some("Inner panic!"));
+///             assert!(panic.location().unwrap().starts_with("src/isolated.rs:"));
+///         }
+///         _ => unreachable!(),
+///     }
+///
+///     match check_deadlock_isolated(std::process::abort, Duration::from_secs(1)).map_err(|e| e.into_error()) {
+///         Err(DeadlockCheckError::Panicked(panic)) => {
+///             assert_eq!(panic.message(), Some("isolated child process was killed by signal 6"));
+///         }
+///         _ => unreachable!(),
+///     }
+///
+///     let error = check_deadlock_isolated(
+///         || std::io::Write::write_all(&mut std::io::stderr(), b"Hello from the child!\n"),
+///         Duration::from_secs(1),
+///     ).unwrap_err();
+///     assert_eq!(error.output(), b"Hello from the child!\n");
+/// }
+/// ```
+pub unsafe fn check_deadlock_isolated<T>(
+    stmt: impl FnOnce() -> T,
+    config: impl Into<Config>,
+) -> Result<IsolatedDeadlock, IsolatedCheckError> {
+    let config = config.into();
+
+    // SAFETY: The name is nul-terminated.
+    let output = unsafe {
+        libc::memfd_create(
+            b"assert-deadlock-output\0".as_ptr().cast(),
+            libc::MFD_CLOEXEC,
+        )
+    };
+    assert!(
+        output >= 0,
+        "check_deadlock_isolated: Could not create output file: {}",
+        std::io::Error::last_os_error(),
+    );
+    // SAFETY: Freshly opened and not owned elsewhere.
+    let output = unsafe { File::from_raw_fd(output) };
+
+    let mut fds = [0; 2];
+    // SAFETY: `fds` is valid for writes of two file descriptors.
+    assert!(
+        unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == 0,
+        "check_deadlock_isolated: Could not create pipe: {}",
+        std::io::Error::last_os_error(),
+    );
+    // SAFETY: Freshly opened and not owned elsewhere.
+    let (reader, mut writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    // SAFETY: The child only runs `stmt` on a copy of this thread, then exits without unwinding or cleanup.
+    // The caller makes sure that no other thread holds a lock the child needs.
+    match unsafe { libc::fork() } {
+        -1 => panic!(
+            "check_deadlock_isolated: Could not fork: {}",
+            std::io::Error::last_os_error()
+        ),
+        0 => {
+            drop(reader);
+            // SAFETY: Replaces standard output and error with the (still open) output file.
+            unsafe {
+                libc::dup2(output.as_raw_fd(), libc::STDOUT_FILENO);
+                libc::dup2(output.as_raw_fd(), libc::STDERR_FILENO);
+            }
+            let _ = writer.write_all(&[STARTED]);
+            let report = encode(panics::catch(|| drop(stmt())));
+            let _ = writer.write_all(&report);
+            // SAFETY: Always safe to call. Skips destructors and `atexit` handlers meant for the parent.
+            unsafe { libc::_exit(0) }
+        }
+        pid => {
+            drop(writer);
+            let mut child = Child {
+                pid,
+                reader,
+                output,
+            };
+            let result = child.check(&config);
+            let output = child.finish();
+            match result {
+                Ok(deadlock) => Ok(IsolatedDeadlock { output, ..deadlock }),
+                Err(error) => Err(IsolatedCheckError {
+                    error: Box::new(error),
+                    output,
+                }),
+            }
+        }
+    }
+}
+
+/// The byte a child writes once it starts running the statement.
+const STARTED: u8 = b'S';
+/// The byte a child writes if the statement returned.
+const RETURNED: u8 = b'R';
+/// The byte a child writes if the statement panicked, followed by the thread name, location and message.
+const PANICKED: u8 = b'P';
+
+/// Encodes a statement's result for the parent process.
+fn encode(result: Result<(), WorkerPanic>) -> Vec<u8> {
+    match result {
+        Ok(()) => vec![RETURNED],
+        Err(panic) => {
+            let mut report = vec![PANICKED];
+            let thread = thread::current();
+            for field in [thread.name(), panic.location(), panic.message()] {
+                // `u32::MAX` marks absent fields.
+                let len = field.and_then(|field| {
+                    u32::try_from(field.len())
+                        .ok()
+                        .filter(|&len| len != u32::MAX)
+                });
+                report.extend_from_slice(&len.unwrap_or(u32::MAX).to_le_bytes());
+                if let (Some(field), Some(_)) = (field, len) {
+                    report.extend_from_slice(field.as_bytes());
+                }
+            }
+            report
+        }
+    }
+}
+
+/// Decodes the fields [`encode`]d after [`PANICKED`].
+fn decode_panic(mut report: &[u8]) -> WorkerPanic {
+    let mut field = || {
+        let len = u32::from_le_bytes(report.get(..4)?.try_into().ok()?);
+        report = &report[4..];
+        if len == u32::MAX {
+            return Some(None);
+        }
+        let len = usize::try_from(len).ok()?;
+        let text = String::from_utf8_lossy(report.get(..len)?).into_owned();
+        report = &report[len..];
+        Some(Some(text))
+    };
+    let thread = field().flatten();
+    let location = field().flatten();
+    let message = field().flatten();
+    panics::from_parts(message, thread, location)
+}
+
+/// A forked child process running a statement.
+///
+/// Dropping this kills and reaps the child.
+struct Child {
+    /// The child's process ID.
+    pid: libc::pid_t,
+    /// The read end of the child's report pipe.
+    reader: File,
+    /// Where the child's standard output and error go.
+    output: File,
+}
+
+impl Child {
+    /// Observes the child as configured.
+    fn check(&mut self, config: &Config) -> Result<IsolatedDeadlock, DeadlockCheckError<()>> {
+        if !self.poll(Instant::now() + config.start_timeout()) {
+            return Err(DeadlockCheckError::NotStarted);
+        }
+        let started = Instant::now();
+        let mut start = [0];
+        if !matches!(self.reader.read(&mut start), Ok(1)) {
+            return Err(self.completed());
+        }
+
+        let mut deadline = started + config.duration();
+        let mut thread_state = None;
+        for _ in 0..VERIFICATION_SAMPLES {
+            if self.poll(deadline) {
+                return Err(self.completed());
+            }
+            if !config.verify_blocked() {
+                break;
+            }
+            match linux::sample_child(self.pid) {
+                Err(_) => (),
+                Ok(state) if state.is_blocked() => thread_state = Some(state),
+                Ok(state) => {
+                    return Err(if self.poll(Instant::now()) {
+                        self.completed()
+                    } else {
+                        DeadlockCheckError::NotBlocked(Box::new(state), None)
+                    })
+                }
+            }
+            deadline = Instant::now() + VERIFICATION_INTERVAL;
+        }
+        Ok(IsolatedDeadlock {
+            started,
+            thread_state,
+            output: Vec::new(),
+        })
+    }
+
+    /// Kills and reaps the child, then returns its captured output.
+    fn finish(mut self) -> Vec<u8> {
+        self.kill();
+        let mut output = Vec::new();
+        let _ = self.output.seek(SeekFrom::Start(0));
+        let _ = self.output.read_to_end(&mut output);
+        output
+    }
+
+    /// Kills and reaps the child, unless that already happened.
+    fn kill(&mut self) {
+        if self.pid > 0 {
+            // SAFETY: The child wasn't reaped yet, so `pid` can't have been reused.
+            unsafe {
+                libc::kill(self.pid, libc::SIGKILL);
+                libc::waitpid(self.pid, ptr::null_mut(), 0);
+            }
+            self.pid = 0;
+        }
+    }
+
+    /// Waits until the pipe is readable (or closed).
+    ///
+    /// Returns `false` iff that didn't happen by `deadline`.
+    fn poll(&self, deadline: Instant) -> bool {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let mut fd = libc::pollfd {
+                fd: self.reader.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+            // SAFETY: Polls exactly the one valid `fd`.
+            match unsafe { libc::poll(ptr::addr_of_mut!(fd), 1, timeout) } {
+                0 => return false,
+                -1 if Instant::now() < deadline => (), // Interrupted.
+                -1 => return false,
+                _ => return true,
+            }
+        }
+    }
+
+    /// Reads the child's report once the statement completed, or describes how the child terminated.
+    fn completed(&mut self) -> DeadlockCheckError<()> {
+        let mut report = Vec::new();
+        let _ = self.reader.read_to_end(&mut report);
+        match report.split_first() {
+            Some((&RETURNED, _)) => DeadlockCheckError::Returned(()),
+            Some((&PANICKED, report)) => DeadlockCheckError::Panicked(decode_panic(report)),
+            _ => {
+                let mut status = 0;
+                // SAFETY: Reaps the child, which exited as the pipe closed.
+                unsafe { libc::waitpid(self.pid, ptr::addr_of_mut!(status), 0) };
+                self.pid = 0;
+                let message = if libc::WIFSIGNALED(status) {
+                    format!(
+                        "isolated child process was killed by signal {}",
+                        libc::WTERMSIG(status)
+                    )
+                } else {
+                    format!(
+                        "isolated child process exited with status {}",
+                        libc::WEXITSTATUS(status)
+                    )
+                };
+                DeadlockCheckError::Panicked(panics::from_parts(Some(message), None, None))
+            }
+        }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// A statement that deadlocked in a child process, as found by [`check_deadlock_isolated`].
+///
+/// The child process was already killed.
+#[derive(Debug)]
+pub struct IsolatedDeadlock {
+    /// When the statement started running.
+    started: Instant,
+    /// The last verified blocked state.
+    thread_state: Option<ThreadState>,
+    /// What the child wrote to its standard output and error.
+    output: Vec<u8>,
+}
+
+impl IsolatedDeadlock {
+    /// When the statement started running in the child process (as far as the parent could tell).
+    #[must_use]
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// The last [`ThreadState`] sampled to verify that the child's thread was blocked.
+    ///
+    /// This is [`None`] unless [verification](`Config::with_verify_blocked`) is enabled.
+    #[must_use]
+    pub fn thread_state(&self) -> Option<&ThreadState> {
+        self.thread_state.as_ref()
+    }
+
+    /// What the child process wrote to its standard output and error before it was killed.
+    #[must_use]
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+/// Why [`check_deadlock_isolated`] found no deadlock, along with the child's captured output.
+#[derive(Debug)]
+pub struct IsolatedCheckError {
+    /// Why the check failed, boxed to keep the [`Result`] small.
+    error: Box<DeadlockCheckError<()>>,
+    /// What the child wrote to its standard output and error.
+    output: Vec<u8>,
+}
+
+impl IsolatedCheckError {
+    /// Why the check failed.
+    #[must_use]
+    pub fn error(&self) -> &DeadlockCheckError<()> {
+        &self.error
+    }
+
+    /// Converts this into why the check failed, discarding the captured output.
+    #[must_use]
+    pub fn into_error(self) -> DeadlockCheckError<()> {
+        *self.error
+    }
+
+    /// What the child process wrote to its standard output and error.
+    #[must_use]
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl Display for IsolatedCheckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.error, f)
+    }
+}
+
+impl Error for IsolatedCheckError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+impl CheckResult for Result<IsolatedDeadlock, IsolatedCheckError> {
+    type Deadlocked = IsolatedDeadlock;
+    type Value = ();
+
+    fn split(self) -> Split<Self::Deadlocked, Self::Value> {
+        match self {
+            Ok(mut deadlock) => {
+                let output = std::mem::take(&mut deadlock.output);
+                (Ok(deadlock), output)
+            }
+            Err(IsolatedCheckError { error, output }) => (Err(*error), output),
+        }
+    }
+}
+
+impl ProvenCycle for IsolatedDeadlock {
+    /// Always [`None`], as the child process's wait-for graph isn't observed.
+    fn proven_cycle(&self) -> Option<&WaitCycle> {
+        None
+    }
+}
@@ -0,0 +1,227 @@
+//! Bookkeeping for worker threads that were abandoned while still running.
+
+#[cfg(target_os = "linux")]
+use crate::linux::{self, Tid};
+use crate::ThreadState;
+use std::{
+    fmt::{self, Display, Formatter},
+    panic::Location,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    thread::{JoinHandle, Thread},
+    time::Instant,
+};
+
+/// The abandoned workers that may still be running.
+static LEAKED: Mutex<Vec<Leak>> = Mutex::new(Vec::new());
+
+/// The maximum number of leaked threads, or `usize::MAX` for no limit.
+static LIMIT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// An abandoned worker thread.
+pub struct Leak {
+    /// Where the check that spawned it was called.
+    pub location: &'static Location<'static>,
+    /// When its statement started running (or, if it didn't, when it was abandoned).
+    pub started: Instant,
+    /// Its kernel thread ID, if it started.
+    #[cfg(target_os = "linux")]
+    pub tid: Option<Tid>,
+    /// Detached when dropped.
+    pub handle: JoinHandle<()>,
+}
+
+/// Locks the registry and prunes threads that exited in the meantime.
+fn leaked() -> MutexGuard<'static, Vec<Leak>> {
+    let mut leaked = LEAKED.lock().unwrap_or_else(PoisonError::into_inner);
+    leaked.retain(|leak| !leak.handle.is_finished());
+    leaked
+}
+
+/// Records an abandoned worker.
+pub fn register(leak: Leak) {
+    leaked().push(leak);
+}
+
+/// Panics iff another worker would exceed the [limit](`set_leaked_threads_limit`).
+#[track_caller]
+pub fn check_limit() {
+    let limit = LIMIT.load(Ordering::Relaxed);
+    let count = leaked().len();
+    assert!(
+        count < limit,
+        "assert_deadlock: Already leaked {} threads, which is the limit. See `assert_deadlock::leaked_threads()`.",
+        count,
+    );
+}
+
+/// Limits how many leaked threads may exist at once.
+///
+/// Every successful deadlock assertion leaks its blocked worker thread (unless [isolated](`crate::check_deadlock_isolated`)),
+/// and so do failed assertions that give up on a still-running statement.
+/// Once `limit` of them are still running, spawning another worker panics instead,
+/// so that a test suite fails with a clear message rather than by running into `RLIMIT_NPROC` or out of memory.
+///
+/// `None` (the default) removes the limit.
+///
+/// # Example
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{sync::Mutex, time::Duration},
+/// # };
+/// use assert_deadlock::{assert_deadlock, leaked_threads, set_leaked_threads_limit};
+///
+/// static MUTEX: Mutex<()> = Mutex::new(());
+///
+/// let guard = MUTEX.lock();
+/// set_leaked_threads_limit(Some(leaked_threads().len() + 1));
+/// assert_deadlock!(forget; MUTEX.lock(), Duration::from_millis(100));
+/// assert_panic!(
+///     {
+///         assert_deadlock!(forget; MUTEX.lock(), Duration::from_millis(100));
+///     },
+///     String,
+///     starts with "assert_deadlock: Already leaked ",
+/// );
+/// ```
+pub fn set_leaked_threads_limit(limit: Option<usize>) {
+    LIMIT.store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// Lists the abandoned worker threads that are still running, with their [current state](`LeakedThread::state`).
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{sync::Mutex, time::Duration};
+/// use assert_deadlock::{assert_deadlock, leaked_threads};
+///
+/// static MUTEX: Mutex<()> = Mutex::new(());
+///
+/// let guard = MUTEX.lock();
+/// assert_deadlock!(forget; MUTEX.lock(), Duration::from_millis(100));
+///
+/// let leaked = leaked_threads();
+/// assert_eq!(leaked.len(), 1);
+/// assert_eq!(leaked[0].location().line(), line!() - 4);
+/// ```
+#[must_use]
+pub fn leaked_threads() -> Vec<LeakedThread> {
+    leaked()
+        .iter()
+        .map(|leak| LeakedThread {
+            location: leak.location,
+            thread: leak.handle.thread().clone(),
+            started: leak.started,
+            #[cfg(target_os = "linux")]
+            state: leak.tid.and_then(|tid| linux::sample(tid).ok()),
+            #[cfg(not(target_os = "linux"))]
+            state: None,
+        })
+        .collect()
+}
+
+/// Prints a summary of the [leaked threads](`leaked_threads`) (if any) to standard error when the process exits normally.
+///
+/// This uses `atexit`, so it also works from tests, where the test harness exits the process.
+/// Calling this more than once has no further effect.
+///
+/// The summary looks like this:
+///
+/// ```text
+/// assert_deadlock: 1 leaked thread(s) at exit:
+///   'assert_deadlock@src/main.rs:6' from src/main.rs:6:5, running for 52.921854ms: S (sleeping) in `futex` at `futex_do_wait`
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// use assert_deadlock::print_leaked_threads_at_exit;
+///
+/// print_leaked_threads_at_exit();
+/// ```
+#[cfg(target_os = "linux")]
+pub fn print_leaked_threads_at_exit() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+
+    extern "C" fn print_summary() {
+        use std::io::{stderr, Write};
+
+        let leaked = leaked_threads();
+        if !leaked.is_empty() {
+            let mut stderr = stderr().lock();
+            let _ = writeln!(
+                stderr,
+                "assert_deadlock: {} leaked thread(s) at exit:",
+                leaked.len()
+            );
+            for leaked in leaked {
+                let _ = writeln!(stderr, "  {leaked}");
+            }
+        }
+    }
+
+    // SAFETY: `print_summary` doesn't unwind.
+    INSTALL.call_once(|| unsafe {
+        libc::atexit(print_summary);
+    });
+}
+
+/// An abandoned worker thread that was still running, as listed by [`leaked_threads`].
+#[derive(Debug, Clone)]
+pub struct LeakedThread {
+    /// Where the check that spawned it was called.
+    location: &'static Location<'static>,
+    /// The thread.
+    thread: Thread,
+    /// When its statement started running.
+    started: Instant,
+    /// Its state when listed.
+    state: Option<ThreadState>,
+}
+
+impl LeakedThread {
+    /// Where the assertion (or check) that spawned this thread was called.
+    #[must_use]
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// The leaked thread.
+    #[must_use]
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    /// When its statement started running (or, if it never did, when it was abandoned).
+    #[must_use]
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Its [`ThreadState`] at the time of listing, if supported on this platform.
+    #[must_use]
+    pub fn state(&self) -> Option<&ThreadState> {
+        self.state.as_ref()
+    }
+}
+
+impl Display for LeakedThread {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' from {}, running for {:?}",
+            self.thread.name().unwrap_or("<unnamed>"),
+            self.location,
+            self.started.elapsed(),
+        )?;
+        if let Some(state) = &self.state {
+            write!(f, ": {state}")?;
+        }
+        Ok(())
+    }
+}
@@ -9,6 +9,8 @@
     clippy::missing_docs_in_private_items,
     clippy::pedantic
 )]
+// Package metadata (license, description etc.) is left to the crate owner.
+#![allow(clippy::cargo_common_metadata)]
 // Debug cleanup. Uncomment before committing.
 #![forbid(
     clippy::dbg_macro,
@@ -17,12 +19,82 @@
     clippy::unimplemented
 )]
 
+mod check;
+mod config;
+mod handle;
+#[cfg(target_os = "linux")]
+mod isolated;
+mod leaks;
+#[cfg(target_os = "linux")]
+mod linux;
+mod livelock;
+mod lock_order;
+mod output;
+mod panics;
+mod report;
+pub mod sync;
+mod thread_state;
+mod wait_for;
+mod watchdog;
+mod worker;
+
+pub use {
+    check::{
+        check_deadlock, check_deadlock_all, check_deadlock_captured, check_deadlock_unchecked,
+        check_deadlock_unchecked_captured, DeadlockCheckError, Deadlocked,
+    },
+    config::Config,
+    handle::DeadlockHandle,
+    leaks::{leaked_threads, set_leaked_threads_limit, LeakedThread},
+    livelock::{check_livelock, LivelockCheckError, Livelocked},
+    output::{worker_output, WorkerOutput},
+    panics::WorkerPanic,
+    thread_state::ThreadState,
+    watchdog::run_with_watchdog,
+};
+#[cfg(target_os = "linux")]
+pub use {
+    isolated::{check_deadlock_isolated, IsolatedCheckError, IsolatedDeadlock},
+    leaks::print_leaked_threads_at_exit,
+};
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::check::check_deadlock_all_captured;
+    pub use crate::config::expecting_completion;
+    pub use crate::livelock::check_livelock_captured;
+    pub use crate::lock_order::{lock_order_mark, lock_order_violations_since};
+    pub use crate::report::{
+        deadlock_all_problems, deadlock_on_problem, lock_order_problems, CallSite, CapturedOutput,
+        CheckResult, ProvenCycle, WaitForCycle, WorkerStack,
+    };
+}
+
 /// Asserts that `$stmt` deadlocks.
 ///
+/// Evaluates to a [`DeadlockHandle`], which can be used to join the worker thread once the deadlock is resolved.
+/// Dropping it abandons the thread instead.
+///
+/// This is implemented on top of [`check_deadlock`].
+///
+/// If `$stmt` is stuck in a cycle of [tracked locks](`sync`), this succeeds as soon as that's found,
+/// and writes a report of the cycle to standard error (which the test harness shows only for failing tests or with `--nocapture`).
+/// See [`DeadlockHandle::cycle`].
+///
 /// # Panics
 ///
 /// Iff `$stmt` doesn't lock up for at least `$duration`.
 ///
+/// This happens as soon as `$stmt` returns, without waiting out the rest of `$duration`.
+///
+/// `$duration` is measured from when `$stmt` starts running on its worker thread.
+/// It can also be a [`Config`], which among other things sets a grace period for the thread to start.
+/// If `$stmt` doesn't start within that, this macro panics with a distinct message.
+///
+/// On Linux, this also panics if the worker thread is still running but not actually blocked in the kernel after `$duration`,
+/// i.e. if `$stmt` is just slow or livelocked. See [`Config::with_verify_blocked`] and [`assert_livelock!`].
+/// The failure message doesn't include the worker thread's stack then, as a running thread isn't interrupted to capture it (see [`Deadlocked::backtrace`]).
+///
 /// # Example
 ///
 /// ```rust
@@ -32,29 +104,50 @@
 /// # };
 /// use assert_deadlock::assert_deadlock;
 ///
-/// let mutex = Mutex::new(());
+/// static MUTEX: Mutex<()> = Mutex::new(());
 ///
 /// assert_panic!(
-///     assert_deadlock!(
-///         { },
-///         Duration::from_secs(1),
-///     ),
-///     &str,
-///     "assert_deadlock! expression returned.",
+///     {
+///         assert_deadlock!(
+///             { },
+///             Duration::from_secs(1),
+///         );
+///     },
+///     String,
+///     starts with "assert_deadlock! failed at ",
 /// );
-/// 
-/// let guard = mutex.lock();
+///
+/// let guard = MUTEX.lock();
 /// assert_deadlock!(
-///     { Box::leak(Box::new(mutex.lock())); },
+///     forget; MUTEX.lock(),
 ///     Duration::from_secs(1),
 /// );
 /// ```
 ///
 /// # Details
 ///
+/// `$stmt` can be any expression, including a block.
+/// Its value is passed back through the [`DeadlockHandle`], so it must be [`Send`] and `'static`.
+///
+/// Prefixing `$stmt` with `forget;` instead [forgets](`core::mem::forget`) the value on the worker thread.
+/// This is useful for lock guards and similar values, which then are never dropped inside the assertion.
+///
+/// `$stmt` runs in a `move` closure on another thread, so it must be [`Send`] and can't borrow from the caller.
+/// Share state with it through `static`s, [`Arc`](`std::sync::Arc`)s or leaked references instead.
+///
+/// ```compile_fail
+/// # use std::{rc::Rc, time::Duration};
+/// use assert_deadlock::assert_deadlock;
+///
+/// let rc = Rc::new(());
+/// assert_deadlock!(drop(rc), Duration::from_secs(1));
+/// ```
+///
+/// The worker thread owns everything `$stmt` captures, so if it deadlocks, those captures are leaked along with it.
+///
 /// If this macro panics from `$stmt` completing, effects of `$stmt` are reliably observable.
 ///
-/// If `$stmt` panics, that panic is propagated:
+/// If `$stmt` panics, that panic is propagated with its original payload:
 ///
 /// ```rust
 /// # use {
@@ -64,7 +157,327 @@
 /// use assert_deadlock::assert_deadlock;
 ///
 /// assert_panic!(
+///     {
+///         assert_deadlock!(
+///             panic!("Inner panic!"),
+///             Duration::from_secs(1),
+///         );
+///     },
+///     &str,
+///     "Inner panic!",
+/// );
+/// ```
+///
+/// The panic hook doesn't report it on the worker thread.
+/// Instead, the calling thread prints it as [`WorkerPanic`] (with its original location and, if enabled, backtrace)
+/// and then resumes unwinding with the payload.
+///
+/// ## Messages
+///
+/// Like [`assert!`], this macro accepts an optional custom message with [`format!`] arguments after `$duration`.
+/// Failure messages always include the stringified statement, the duration and the call site:
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::time::Duration,
+/// # };
+/// use assert_deadlock::assert_deadlock;
+///
+/// let account = "alice";
+/// assert_panic!(
+///     {
+///         assert_deadlock!(1 + 1, Duration::from_secs(1), "transfer from {}", account);
+///     },
+///     String,
+///     starts with "transfer from alice\nassert_deadlock! failed at src/lib.rs:",
+/// );
+/// ```
+///
+/// The other assertion macros in this crate accept custom messages the same way.
+///
+/// ## Borrowing
+///
+/// Prefixing `$stmt` with `unsafe;` runs it in a borrowing closure instead, through [`check_deadlock_unchecked`].
+/// This can be combined with `forget;`, in that order.
+/// The invocation must then be placed in an `unsafe` block,
+/// and everything `$stmt` borrows must stay valid for as long as the worker thread runs (see there).
+///
+/// ```rust
+/// # use std::{sync::Mutex, time::Duration};
+/// use assert_deadlock::assert_deadlock;
+///
+/// let mutex = Mutex::new(());
+///
+/// let guard = mutex.lock();
+/// unsafe {
+///     //SAFETY: The process exits before `mutex` goes out of scope.
 ///     assert_deadlock!(
+///         unsafe; forget; mutex.lock(),
+///         Duration::from_secs(1),
+///     );
+/// }
+/// std::process::exit(0);
+/// ```
+///
+/// ## Isolation
+///
+/// On Linux, prefixing `$stmt` with `unsafe; isolated;` runs it in a forked child process instead, through [`check_deadlock_isolated`].
+/// The child is killed once the assertion is done, so nothing is leaked into the test process,
+/// and `$stmt` may borrow freely (from its copy of the calling thread).
+///
+/// Forking a multithreaded process is only sound under the conditions listed there,
+/// so the invocation must then be placed in an `unsafe` block.
+///
+/// The macro then evaluates to an [`IsolatedDeadlock`] rather than a [`DeadlockHandle`],
+/// and effects of `$stmt` aren't observable by the caller. See there for further caveats.
+///
+/// ```rust
+/// # use std::{sync::Mutex, time::Duration};
+/// use assert_deadlock::assert_deadlock;
+///
+/// let mutex = Mutex::new(());
+///
+/// # #[cfg(target_os = "linux")] {
+/// let guard = mutex.lock().unwrap();
+/// unsafe {
+///     //SAFETY: Doctests run single-threaded.
+///     assert_deadlock!(unsafe; isolated; mutex.lock(), Duration::from_secs(1));
+/// }
+/// drop(guard);
+///
+/// // The child process and its lock attempt are gone.
+/// drop(mutex.try_lock().unwrap());
+/// # }
+/// ```
+///
+/// Failure reports include what the child wrote to its standard output and error (see there for limitations):
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{io::Write, time::Duration},
+/// # };
+/// use assert_deadlock::assert_deadlock;
+///
+/// # #[cfg(target_os = "linux")] {
+/// assert_panic!(
+///     unsafe {
+///         //SAFETY: Doctests run single-threaded.
+///         assert_deadlock!(
+///             unsafe; isolated; std::io::stderr().write_all(b"Not blocked!\n"),
+///             Duration::from_secs(1),
+///         );
+///     },
+///     String,
+///     contains "returned within 1s.\n\ncaptured output:\nNot blocked!",
+/// );
+/// # }
+/// ```
+///
+/// Without isolation, failure reports include what `$stmt` wrote to its [`worker_output`] instead,
+/// and panics are reported once, from the calling thread.
+/// [`print!`] and [`eprint!`] on the worker thread aren't captured, see there.
+#[macro_export]
+macro_rules! assert_deadlock {
+    (@assert $check:path, $deadlocked:path, $call_site:expr, $stmt:expr, $duration:expr, ($($message:tt)*)) => {{
+        use $crate::{Config, DeadlockCheckError, __private::{CapturedOutput, CheckResult, ProvenCycle}};
+
+        let call_site = $call_site;
+        let config = Config::from($duration);
+        let (result, output) = CheckResult::split($check($stmt, config.clone()));
+        let output = CapturedOutput(&output);
+        match result {
+            Ok(deadlocked) => {
+                // Still blocked, all good.
+                call_site.note_cycle(deadlocked.proven_cycle());
+                $deadlocked(deadlocked)
+            }
+            Err(DeadlockCheckError::Returned(_)) => call_site.fail(
+                format_args!("returned within {:?}.{}", config.duration(), output),
+                $crate::__message!($($message)*),
+            ),
+            Err(DeadlockCheckError::Panicked(panic)) => {
+                output.eprint();
+                panic.resume()
+            }
+            Err(DeadlockCheckError::NotStarted) => call_site.fail(
+                format_args!("did not start within {:?}.{}", config.start_timeout(), output),
+                $crate::__message!($($message)*),
+            ),
+            Err(DeadlockCheckError::NotBlocked(state, backtrace)) => call_site.fail(
+                format_args!(
+                    "still running, not blocked, after {:?}: {}{}{}",
+                    config.duration(),
+                    state,
+                    $crate::__private::WorkerStack(backtrace.as_ref()),
+                    output,
+                ),
+                $crate::__message!($($message)*),
+            ),
+        }
+    }};
+    (unsafe; isolated; $stmt:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_deadlock!(
+            @assert $crate::check_deadlock_isolated,
+            std::convert::identity,
+            $crate::__call_site!("assert_deadlock", $stmt),
+            || $stmt,
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+    (unsafe; forget; $stmt:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_deadlock!(
+            @assert $crate::check_deadlock_unchecked_captured,
+            $crate::Deadlocked::into_handle,
+            $crate::__call_site!("assert_deadlock", $stmt),
+            || std::mem::forget($stmt),
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+    (unsafe; $stmt:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_deadlock!(
+            @assert $crate::check_deadlock_unchecked_captured,
+            $crate::Deadlocked::into_handle,
+            $crate::__call_site!("assert_deadlock", $stmt),
+            || $stmt,
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+    (forget; $stmt:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_deadlock!(
+            @assert $crate::check_deadlock_captured,
+            $crate::Deadlocked::into_handle,
+            $crate::__call_site!("assert_deadlock", $stmt),
+            move || std::mem::forget($stmt),
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+    ($stmt:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_deadlock!(
+            @assert $crate::check_deadlock_captured,
+            $crate::Deadlocked::into_handle,
+            $crate::__call_site!("assert_deadlock", $stmt),
+            move || $stmt,
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+}
+
+/// Asserts that `$expr` does **not** deadlock, i.e. that it completes within `$duration`.
+///
+/// This is the counterpart to [`assert_deadlock!`], for checking that a fix made the same statement finish.
+///
+/// Evaluates to the value of `$expr`.
+///
+/// # Panics
+///
+/// Iff `$expr` is still blocked after `$duration`,
+/// or if it didn't start within the [`Config`]'s [start timeout](`Config::with_start_timeout`).
+///
+/// On Linux, the failure message then includes where the worker thread is stuck (see [`Deadlocked::backtrace`]).
+///
+/// # Example
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{sync::Mutex, time::Duration},
+/// # };
+/// use assert_deadlock::assert_no_deadlock;
+///
+/// static MUTEX: Mutex<i32> = Mutex::new(1);
+///
+/// let value = assert_no_deadlock!(
+///     *MUTEX.lock().unwrap() + 1,
+///     Duration::from_secs(1),
+/// );
+/// assert_eq!(value, 2);
+///
+/// let guard = MUTEX.lock();
+/// assert_panic!(
+///     assert_no_deadlock!(
+///         forget; MUTEX.lock(),
+///         Duration::from_secs(1),
+///     ),
+///     String,
+///     contains "`MUTEX.lock()` still blocked after 1s.",
+/// );
+/// ```
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{sync::Mutex, time::Duration},
+/// # };
+/// use assert_deadlock::assert_no_deadlock;
+///
+/// static MUTEX: Mutex<()> = Mutex::new(());
+///
+/// # #[cfg(target_os = "linux")] {
+/// let guard = MUTEX.lock();
+/// assert_panic!(
+///     assert_no_deadlock!(forget; MUTEX.lock(), Duration::from_millis(100)),
+///     String,
+///     contains "still blocked after 100ms.\n\nworker thread stack backtrace:\n",
+/// );
+/// # }
+/// ```
+///
+/// If `$expr` is stuck in a cycle of [tracked locks](`sync`), the assertion fails as soon as that's found,
+/// and the failure message reports which thread holds and waits for which lock, and where:
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{thread, time::Duration},
+/// # };
+/// use assert_deadlock::{assert_no_deadlock, sync::Mutex};
+///
+/// static ACCOUNTS: Mutex<()> = Mutex::named("accounts", ());
+/// static LEDGER: Mutex<()> = Mutex::named("ledger", ());
+///
+/// thread::Builder::new()
+///     .name("transfer".to_string())
+///     .spawn(|| {
+///         let _ledger = LEDGER.lock().unwrap();
+///         thread::sleep(Duration::from_millis(100));
+///         drop(ACCOUNTS.lock());
+///     })
+///     .unwrap();
+/// thread::sleep(Duration::from_millis(50));
+///
+/// assert_panic!(
+///     assert_no_deadlock!(
+///         {
+///             let _accounts = ACCOUNTS.lock().unwrap();
+///             drop(LEDGER.lock());
+///         },
+///         Duration::from_secs(10),
+///     ),
+///     String,
+///     contains "and waits for `ledger` (src/lib.rs:",
+/// );
+/// ```
+///
+/// # Details
+///
+/// If `$expr` panics, that panic is propagated:
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::time::Duration,
+/// # };
+/// use assert_deadlock::assert_no_deadlock;
+///
+/// assert_panic!(
+///     assert_no_deadlock!(
 ///         panic!("Inner panic!"),
 ///         Duration::from_secs(1),
 ///     ),
@@ -72,49 +485,759 @@
 ///     "Inner panic!",
 /// );
 /// ```
+///
+/// As with [`assert_deadlock!`], `$expr` runs in a `move` closure on another thread.
+/// If the assertion fails, the blocked thread is leaked along with everything `$expr` captured.
+///
+/// `forget;` and `unsafe;` work here too, with the same caveats.
+///
+/// ## Thread-bound statements
+///
+/// Prefixing `$expr` with `local;` runs it on the calling thread instead, via [`run_with_watchdog`].
+/// `$expr` then doesn't have to be [`Send`] and may borrow freely,
+/// but since the calling thread can't be interrupted, a timeout **aborts the process** after reporting to standard error.
+///
+/// ```rust
+/// # use std::{cell::RefCell, rc::Rc, time::Duration};
+/// use assert_deadlock::assert_no_deadlock;
+///
+/// let cell = Rc::new(RefCell::new(1));
+///
+/// let value = assert_no_deadlock!(local; *cell.borrow() + 1, Duration::from_secs(1));
+/// assert_eq!(value, 2);
+/// ```
+///
+/// There is no such mode for [`assert_deadlock!`], as a deadlocked calling thread could never report success.
 #[macro_export]
-macro_rules! assert_deadlock {
-    ($stmt:stmt, $duration:expr$(,)?) => {{
-        use std::{
-            any::Any,
-            mem::transmute,
-            panic::{catch_unwind, resume_unwind, UnwindSafe},
-            sync::{Arc, Mutex},
-            thread,
-        };
+macro_rules! assert_no_deadlock {
+    (@assert $check:path, $call_site:expr, $expr:expr, $duration:expr, ($($message:tt)*)) => {{
+        use $crate::{Config, DeadlockCheckError, __private::CapturedOutput};
+
+        let call_site = $call_site;
+        let config = $crate::__private::expecting_completion(Config::from($duration));
+        let (result, output) = $check($expr, config.clone());
+        let output = CapturedOutput(&output);
+        match result {
+            Err(DeadlockCheckError::Returned(value)) => value,
+            Err(DeadlockCheckError::Panicked(panic)) => {
+                output.eprint();
+                panic.resume()
+            }
+            Err(DeadlockCheckError::NotStarted) => call_site.fail(
+                format_args!("did not start within {:?}.{}", config.start_timeout(), output),
+                $crate::__message!($($message)*),
+            ),
+            Ok(deadlocked) => call_site.fail(
+                format_args!(
+                    "still blocked after {:?}.{}{}{}",
+                    // A cycle proves the deadlock before the full duration.
+                    deadlocked.cycle().map_or(config.duration(), |_| deadlocked.started().elapsed()),
+                    $crate::__private::WaitForCycle(deadlocked.cycle()),
+                    $crate::__private::WorkerStack(deadlocked.backtrace().as_ref()),
+                    output,
+                ),
+                $crate::__message!($($message)*),
+            ),
+            Err(DeadlockCheckError::NotBlocked(state, backtrace)) => call_site.fail(
+                format_args!(
+                    "still running after {:?}: {}{}{}",
+                    config.duration(),
+                    state,
+                    $crate::__private::WorkerStack(backtrace.as_ref()),
+                    output,
+                ),
+                $crate::__message!($($message)*),
+            ),
+        }
+    }};
+    (local; $expr:expr, $duration:expr$(, $($arg:tt)*)?) => {{
+        let call_site = $crate::__call_site!("assert_no_deadlock", $expr);
+        let config = $crate::Config::from($duration);
+        $crate::run_with_watchdog(
+            || $expr,
+            config.clone(),
+            call_site.report(
+                format_args!("still blocked after {:?}, aborting.", config.duration()),
+                $crate::__message!($($($arg)*)?),
+            ),
+        )
+    }};
+    (unsafe; forget; $expr:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_no_deadlock!(
+            @assert $crate::check_deadlock_unchecked_captured,
+            $crate::__call_site!("assert_no_deadlock", $expr),
+            || std::mem::forget($expr),
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+    (unsafe; $expr:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_no_deadlock!(
+            @assert $crate::check_deadlock_unchecked_captured,
+            $crate::__call_site!("assert_no_deadlock", $expr),
+            || $expr,
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+    (forget; $expr:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_no_deadlock!(
+            @assert $crate::check_deadlock_captured,
+            $crate::__call_site!("assert_no_deadlock", $expr),
+            move || std::mem::forget($expr),
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+    ($expr:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_no_deadlock!(
+            @assert $crate::check_deadlock_captured,
+            $crate::__call_site!("assert_no_deadlock", $expr),
+            move || $expr,
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+}
+
+/// Asserts that `$stmt` stays blocked until `$trigger` runs.
+///
+/// First asserts that `$stmt` deadlocks for `$duration` (like [`assert_deadlock!`]),
+/// then calls the closure `$trigger` on the calling thread,
+/// and finally asserts that `$stmt` completes within `$release_duration` (which defaults to `$duration`).
+///
+/// Evaluates to the value of `$stmt`.
+///
+/// # Panics
+///
+/// Iff `$stmt` completes before `$trigger` runs, or is still blocked `$release_duration` after.
+///
+/// If `$stmt` or `$trigger` panic, that panic is propagated.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{sync::mpsc, time::Duration};
+/// use assert_deadlock::assert_blocks_until;
+///
+/// let (sender, receiver) = mpsc::channel();
+///
+/// let value = assert_blocks_until!(
+///     receiver.recv().unwrap(),
+///     || sender.send(1).unwrap(),
+///     Duration::from_secs(1),
+/// );
+/// assert_eq!(value, 1);
+/// ```
+///
+/// As with [`assert_deadlock!`], `$stmt` may be prefixed with `forget;`:
+///
+/// ```rust
+/// # use std::{sync::Mutex, time::Duration};
+/// use assert_deadlock::assert_blocks_until;
+///
+/// static MUTEX: Mutex<()> = Mutex::new(());
+///
+/// let guard = MUTEX.lock().unwrap();
+/// assert_blocks_until!(
+///     forget; MUTEX.lock(),
+///     || drop(guard),
+///     Duration::from_secs(1),
+/// );
+/// assert!(MUTEX.try_lock().is_err());
+/// ```
+#[macro_export]
+macro_rules! assert_blocks_until {
+    (@assert $call_site:expr, $stmt:expr, $trigger:expr, $duration:expr, ($($release_duration:expr)?), ($($message:tt)*)) => {{
+        let call_site = $call_site;
+        let config = $crate::Config::from($duration);
+        let release_duration = config.duration();
+        $(let release_duration: std::time::Duration = $release_duration;)?
+        let handle = $crate::assert_deadlock!(
+            @assert $crate::check_deadlock_captured,
+            $crate::Deadlocked::into_handle,
+            call_site,
+            $stmt,
+            config,
+            ($($message)*)
+        );
+        ($trigger)();
+        match handle.try_join_within(release_duration) {
+            Ok(Ok(value)) => value,
+            Ok(Err(panic)) => panic.resume(),
+            Err(handle) => call_site.fail(
+                format_args!(
+                    "still blocked {:?} after the trigger ran.{}{}",
+                    release_duration,
+                    $crate::__private::WorkerStack(handle.backtrace().as_ref()),
+                    $crate::__private::CapturedOutput(&handle.take_output()),
+                ),
+                $crate::__message!($($message)*),
+            ),
+        }
+    }};
+    (forget; $stmt:expr, $trigger:expr, $duration:expr, $fmt:literal$($arg:tt)*) => {
+        $crate::assert_blocks_until!(
+            @assert $crate::__call_site!("assert_blocks_until", $stmt),
+            move || std::mem::forget($stmt),
+            $trigger,
+            $duration,
+            (),
+            ($fmt$($arg)*)
+        )
+    };
+    (forget; $stmt:expr, $trigger:expr, $duration:expr$(, $release_duration:expr)?$(, $($fmt:literal$($arg:tt)*)?)?) => {
+        $crate::assert_blocks_until!(
+            @assert $crate::__call_site!("assert_blocks_until", $stmt),
+            move || std::mem::forget($stmt),
+            $trigger,
+            $duration,
+            ($($release_duration)?),
+            ($($($fmt$($arg)*)?)?)
+        )
+    };
+    ($stmt:expr, $trigger:expr, $duration:expr, $fmt:literal$($arg:tt)*) => {
+        $crate::assert_blocks_until!(
+            @assert $crate::__call_site!("assert_blocks_until", $stmt),
+            move || $stmt,
+            $trigger,
+            $duration,
+            (),
+            ($fmt$($arg)*)
+        )
+    };
+    ($stmt:expr, $trigger:expr, $duration:expr$(, $release_duration:expr)?$(, $($fmt:literal$($arg:tt)*)?)?) => {
+        $crate::assert_blocks_until!(
+            @assert $crate::__call_site!("assert_blocks_until", $stmt),
+            move || $stmt,
+            $trigger,
+            $duration,
+            ($($release_duration)?),
+            ($($($fmt$($arg)*)?)?)
+        )
+    };
+}
+
+/// Asserts that all of several statements deadlock together.
+///
+/// Each statement runs on its own worker thread, as with [`assert_deadlock!`],
+/// and must still be blocked once `$duration` passed since the last of them started.
+/// `$duration` can also be a [`Config`], e.g. to [start them together](`Config::with_start_barrier`).
+///
+/// Evaluates to a [`Vec`] of [`DeadlockHandle`]s, in order, with each statement's value boxed as [`dyn Any + Send`](`std::any::Any`).
+///
+/// This is implemented on top of [`check_deadlock_all`].
+///
+/// # Panics
+///
+/// Iff any statement doesn't lock up. The failure message lists what happened to each of them.
+///
+/// Panics of the statements are included in that message rather than propagated, as there may be several.
+///
+/// # Example
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{sync::Mutex, thread, time::Duration},
+/// # };
+/// use assert_deadlock::{assert_deadlock_all, Config};
+///
+/// static A: Mutex<()> = Mutex::new(());
+/// static B: Mutex<()> = Mutex::new(());
+///
+/// let line = line!() + 1;
+/// let handles = assert_deadlock_all!(
+///     [
+///         {
+///             let _a = A.lock();
+///             thread::sleep(Duration::from_millis(100));
+///             drop(B.lock());
+///         },
+///         {
+///             let _b = B.lock();
+///             thread::sleep(Duration::from_millis(100));
+///             drop(A.lock());
+///         },
+///     ],
+///     Config::new(Duration::from_secs(1)).with_start_barrier(true),
+/// );
+///
+/// // The worker threads are named after the call site.
+/// let name = format!("assert_deadlock@{}:{}", file!(), line);
+/// assert!(handles.iter().all(|handle| handle.thread().name() == Some(name.as_str())));
+///
+/// static C: Mutex<()> = Mutex::new(());
+///
+/// let guard = C.lock();
+/// assert_panic!(
+///     {
+///         assert_deadlock_all!(
+///             [1 + 1, drop(C.lock()), panic!("Inner panic!")],
+///             Duration::from_millis(100),
+///         );
+///     },
+///     String,
+///     contains "not all blocked after 100ms:\n  `1 + 1` returned.\n  `drop(C.lock())` still blocked.\n  `panic!(\"Inner panic!\")` panicked: thread 'assert_deadlock@",
+/// );
+/// ```
+///
+/// # Details
+///
+/// Each statement runs in its own `move` closure, so statements can't share captured variables.
+/// Use `static`s, or clone [`Arc`](`std::sync::Arc`)s into separate variables beforehand.
+#[macro_export]
+macro_rules! assert_deadlock_all {
+    ([$($stmt:expr),+ $(,)?], $duration:expr$(, $($arg:tt)*)?) => {{
+        use std::any::Any;
 
-        let stmt: Box<dyn FnOnce() + UnwindSafe + '_> = Box::new(|| {
-            {}
-            $stmt
-        });
-        let stmt: Box<dyn FnOnce() + UnwindSafe + Send + 'static> = unsafe {
-            //SAFETY: Essentially the same type, externally synchronised.
-            transmute(stmt)
+        let call_site = $crate::__call_site!("assert_deadlock_all", [$($stmt),+]);
+        let config = $crate::Config::from($duration);
+        let results = $crate::__private::check_deadlock_all_captured(
+            vec![$(
+                Box::new(move || Box::new($stmt) as Box<dyn Any + Send>)
+                    as Box<dyn FnOnce() -> Box<dyn Any + Send> + Send>
+            ),+],
+            config.clone(),
+        );
+        if let Some(problems) = $crate::__private::deadlock_all_problems(
+            &[$(stringify!($stmt)),+],
+            &results,
+            &config,
+        ) {
+            call_site.fail(format_args!("{}", problems), $crate::__message!($($($arg)*)?))
+        }
+        results
+            .into_iter()
+            .filter_map(|(result, _)| result.ok())
+            .map($crate::Deadlocked::into_handle)
+            .collect::<Vec<_>>()
+    }};
+}
+
+/// Asserts that `$stmt` deadlocks waiting on `$lock` in particular.
+///
+/// `$lock` must be a [tracked lock or condition variable](`sync`), which `$stmt` must use through the same type.
+/// Without this, a test would also pass if `$stmt` deadlocks on another lock, e.g. because the test setup itself is broken.
+///
+/// Evaluates to a [`DeadlockHandle`], like [`assert_deadlock!`], and accepts `forget;` the same way.
+///
+/// # Panics
+///
+/// Like [`assert_deadlock!`], and also iff the worker thread is blocked on anything other than `$lock`.
+/// The failure message then names the lock it actually waits on, and where it called to acquire it.
+///
+/// # Example
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::time::Duration,
+/// # };
+/// use assert_deadlock::{assert_deadlock_on, sync::Mutex};
+///
+/// static ACCOUNTS: Mutex<()> = Mutex::named("accounts", ());
+/// static LEDGER: Mutex<()> = Mutex::named("ledger", ());
+///
+/// let guard = ACCOUNTS.lock().unwrap();
+/// assert_deadlock_on!(forget; ACCOUNTS.lock(), &ACCOUNTS, Duration::from_millis(100));
+///
+/// let guard = LEDGER.lock().unwrap();
+/// assert_panic!(
+///     {
+///         assert_deadlock_on!(forget; LEDGER.lock(), &ACCOUNTS, Duration::from_millis(100));
+///     },
+///     String,
+///     contains "`LEDGER.lock()` blocked on `ledger` (src/lib.rs:",
+/// );
+/// ```
+#[macro_export]
+macro_rules! assert_deadlock_on {
+    (@assert $call_site:expr, $stmt:expr, $lock:expr, $duration:expr, ($($message:tt)*)) => {{
+        let call_site = $call_site;
+        let lock = $lock;
+        let handle = $crate::assert_deadlock!(
+            @assert $crate::check_deadlock_captured,
+            $crate::Deadlocked::into_handle,
+            call_site,
+            $stmt,
+            $duration,
+            ($($message)*)
+        );
+        if let Some(problem) = $crate::__private::deadlock_on_problem(handle.thread(), &lock) {
+            call_site.fail(
+                format_args!(
+                    "{}{}",
+                    problem,
+                    $crate::__private::WorkerStack(handle.backtrace().as_ref()),
+                ),
+                $crate::__message!($($message)*),
+            )
+        }
+        handle
+    }};
+    (forget; $stmt:expr, $lock:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_deadlock_on!(
+            @assert $crate::__call_site!("assert_deadlock_on", $stmt),
+            move || std::mem::forget($stmt),
+            $lock,
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+    ($stmt:expr, $lock:expr, $duration:expr$(, $($arg:tt)*)?) => {
+        $crate::assert_deadlock_on!(
+            @assert $crate::__call_site!("assert_deadlock_on", $stmt),
+            move || $stmt,
+            $lock,
+            $duration,
+            ($($($arg)*)?)
+        )
+    };
+}
+
+/// Asserts that `$stmt` blocks for at least `$min` but completes within `$max`.
+///
+/// This is meant for timed waits, like [`Condvar::wait_timeout`](`std::sync::Condvar::wait_timeout`)
+/// or [`Receiver::recv_timeout`](`std::sync::mpsc::Receiver::recv_timeout`).
+///
+/// `$max` can also be a [`Config`].
+///
+/// Evaluates to the value of `$stmt`.
+///
+/// # Panics
+///
+/// Iff `$stmt` completes before `$min` or is still blocked after `$max`.
+/// Both failure messages include the measured blocking time.
+///
+/// If `$stmt` panics, that panic is propagated.
+///
+/// # Example
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{sync::mpsc, time::Duration},
+/// # };
+/// use assert_deadlock::assert_blocks_for;
+///
+/// let (sender, receiver) = mpsc::channel::<()>();
+///
+/// let result = assert_blocks_for!(
+///     receiver.recv_timeout(Duration::from_millis(100)),
+///     Duration::from_millis(50),
+///     Duration::from_secs(1),
+/// );
+/// assert_eq!(result, Err(mpsc::RecvTimeoutError::Timeout));
+///
+/// assert_panic!(
+///     assert_blocks_for!(
+///         (),
+///         Duration::from_millis(50),
+///         Duration::from_secs(1),
+///     ),
+///     String,
+///     contains "`()` returned after ",
+/// );
+/// # drop(sender);
+/// ```
+///
+/// If `$stmt` is stuck in a cycle of [tracked locks](`sync`), the assertion fails as soon as that's found,
+/// with the cycle in the failure message:
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{thread, time::Duration},
+/// # };
+/// use assert_deadlock::{assert_blocks_for, sync::Mutex};
+///
+/// static ACCOUNTS: Mutex<()> = Mutex::named("accounts", ());
+/// static LEDGER: Mutex<()> = Mutex::named("ledger", ());
+///
+/// thread::spawn(|| {
+///     let _ledger = LEDGER.lock().unwrap();
+///     thread::sleep(Duration::from_millis(100));
+///     drop(ACCOUNTS.lock());
+/// });
+/// thread::sleep(Duration::from_millis(50));
+///
+/// assert_panic!(
+///     assert_blocks_for!(
+///         {
+///             let _accounts = ACCOUNTS.lock().unwrap();
+///             drop(LEDGER.lock());
+///         },
+///         Duration::from_millis(10),
+///         Duration::from_secs(10),
+///     ),
+///     String,
+///     contains "\n\nwait-for cycle: thread 'assert_deadlock@",
+/// );
+/// ```
+#[macro_export]
+macro_rules! assert_blocks_for {
+    ($stmt:expr, $min:expr, $max:expr$(, $($arg:tt)*)?) => {{
+        use {
+            std::time::{Duration, Instant},
+            $crate::{Config, DeadlockCheckError, __private::CapturedOutput},
         };
-        let panic_slot: Arc<Mutex<Option<Box<dyn Any + Send + 'static>>>> = Arc::default();
-        let _ = thread::spawn({
-            let panic_slot = panic_slot.clone();
+
+        let call_site = $crate::__call_site!("assert_blocks_for", $stmt);
+        let min: Duration = $min;
+        let config = $crate::__private::expecting_completion(Config::from($max));
+        let (result, output) = $crate::check_deadlock_captured(
             move || {
-                panic_slot
-                    .lock()
-                    .unwrap()
-                    .replace(catch_unwind(stmt).map_or_else(
-                        |error| error,
-                        |()| Box::new("assert_deadlock! expression returned."),
-                    ));
+                let started = Instant::now();
+                let value = $stmt;
+                (value, started.elapsed())
+            },
+            config.clone(),
+        );
+        let output = CapturedOutput(&output);
+        match result {
+            Err(DeadlockCheckError::Returned((value, blocked))) => {
+                if blocked < min {
+                    call_site.fail(
+                        format_args!("returned after {:?}, before the minimum of {:?}.{}", blocked, min, output),
+                        $crate::__message!($($($arg)*)?),
+                    )
+                }
+                value
             }
-        });
-        thread::sleep($duration);
-        if let Ok(mut guard) = panic_slot.try_lock() {
-            let panic = guard.take();
-            drop(guard);
-            if let Some(panic) = panic {
-                resume_unwind(panic); // Hm, doesn't seem to quite work yet with inner panics.
-            } else {
-                panic!("assert_deadlock!: Could not start `$stmt` during `$duration`");
+            Err(DeadlockCheckError::Panicked(panic)) => {
+                output.eprint();
+                panic.resume()
             }
-        } else {
-            // Still locked, all good.
-        };
+            Err(DeadlockCheckError::NotStarted) => call_site.fail(
+                format_args!("did not start within {:?}.{}", config.start_timeout(), output),
+                $crate::__message!($($($arg)*)?),
+            ),
+            // A cycle proves the deadlock before the maximum.
+            Ok(deadlocked) if deadlocked.cycle().is_some() => call_site.fail(
+                format_args!(
+                    "deadlocked after {:?}.{}{}{}",
+                    deadlocked.started().elapsed(),
+                    $crate::__private::WaitForCycle(deadlocked.cycle()),
+                    $crate::__private::WorkerStack(deadlocked.backtrace().as_ref()),
+                    output,
+                ),
+                $crate::__message!($($($arg)*)?),
+            ),
+            Ok(deadlocked) => call_site.fail(
+                format_args!(
+                    "still blocked after {:?}, past the maximum of {:?}.{}{}",
+                    deadlocked.started().elapsed(),
+                    config.duration(),
+                    $crate::__private::WorkerStack(deadlocked.backtrace().as_ref()),
+                    output,
+                ),
+                $crate::__message!($($($arg)*)?),
+            ),
+            Err(DeadlockCheckError::NotBlocked(state, backtrace)) => call_site.fail(
+                format_args!(
+                    "still running after more than the maximum of {:?}: {}{}{}",
+                    config.duration(),
+                    state,
+                    $crate::__private::WorkerStack(backtrace.as_ref()),
+                    output,
+                ),
+                $crate::__message!($($($arg)*)?),
+            ),
+        }
+    }};
+}
+
+/// Asserts that `$stmt` livelocks, i.e. never completes **and** keeps consuming CPU time for `$duration`.
+///
+/// This is the counterpart of [`assert_deadlock!`] for spinlocks and endless retry loops,
+/// which never block in the kernel.
+///
+/// Evaluates to a [`DeadlockHandle`], like [`assert_deadlock!`].
+///
+/// This is implemented on top of [`check_livelock`], so it's currently only supported on Linux.
+///
+/// # Panics
+///
+/// Iff `$stmt` completes, stops consuming CPU time (e.g. because it blocks) in either half of `$duration`,
+/// or if per-thread CPU time is unavailable on this platform.
+///
+/// If `$stmt` panics, that panic is propagated.
+///
+/// # Example
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{sync::{Mutex, atomic::{AtomicBool, Ordering}}, time::Duration},
+/// # };
+/// use assert_deadlock::assert_livelock;
+///
+/// static LOCKED: AtomicBool = AtomicBool::new(false);
+/// static MUTEX: Mutex<()> = Mutex::new(());
+///
+/// # #[cfg(target_os = "linux")] {
+/// LOCKED.store(true, Ordering::Release);
+/// assert_livelock!(
+///     while LOCKED.swap(true, Ordering::Acquire) { std::hint::spin_loop() },
+///     Duration::from_millis(100),
+/// );
+///
+/// let guard = MUTEX.lock();
+/// assert_panic!(
+///     {
+///         assert_livelock!(
+///             std::mem::forget(MUTEX.lock()),
+///             Duration::from_millis(100),
+///         );
+///     },
+///     String,
+///     contains "stopped consuming CPU time within 100ms: S (sleeping)",
+/// );
+/// # }
+/// ```
+#[macro_export]
+macro_rules! assert_livelock {
+    ($stmt:expr, $duration:expr$(, $($arg:tt)*)?) => {{
+        use $crate::{Config, LivelockCheckError, __private::CapturedOutput};
+
+        let call_site = $crate::__call_site!("assert_livelock", $stmt);
+        let config = Config::from($duration);
+        let (result, output) = $crate::__private::check_livelock_captured(move || $stmt, config.clone());
+        let output = CapturedOutput(&output);
+        match result {
+            Ok(livelocked) => livelocked.into_handle(), // Still spinning, all good.
+            Err(LivelockCheckError::Returned(_)) => call_site.fail(
+                format_args!("returned within {:?}.{}", config.duration(), output),
+                $crate::__message!($($($arg)*)?),
+            ),
+            Err(LivelockCheckError::Panicked(panic)) => {
+                output.eprint();
+                panic.resume()
+            }
+            Err(LivelockCheckError::NotStarted) => call_site.fail(
+                format_args!("did not start within {:?}.{}", config.start_timeout(), output),
+                $crate::__message!($($($arg)*)?),
+            ),
+            Err(LivelockCheckError::Idle(state, backtrace)) => call_site.fail(
+                format_args!(
+                    "stopped consuming CPU time within {:?}: {}{}{}",
+                    config.duration(),
+                    state,
+                    $crate::__private::WorkerStack(backtrace.as_ref()),
+                    output,
+                ),
+                $crate::__message!($($($arg)*)?),
+            ),
+            Err(LivelockCheckError::Unsupported) => call_site.fail(
+                format_args!("can't be checked, as per-thread CPU time is unavailable on this platform.{}", output),
+                $crate::__message!($($($arg)*)?),
+            ),
+        }
+    }};
+}
+
+/// Asserts that `$stmt` causes a [lock-order violation](`sync::lock_order_violations`) among [tracked locks](`sync`).
+///
+/// This catches lock-order inversions even if the timing doesn't let them deadlock.
+/// `$stmt` runs on the calling thread (and may spawn others), and only violations that occur while it runs count.
+///
+/// Evaluates to the value of `$stmt`.
+///
+/// The lock order is global, so concurrently running tests that use the same locks may affect the result.
+/// Give the locks of each test their own [names](`sync::Mutex::named`) (or none) to keep it apart.
+///
+/// # Panics
+///
+/// Iff no violation occurred while `$stmt` ran.
+///
+/// # Example
+///
+/// ```rust
+/// use assert_deadlock::{assert_lock_order_violation, sync::Mutex};
+///
+/// static ACCOUNTS: Mutex<()> = Mutex::named("accounts", ());
+/// static LEDGER: Mutex<()> = Mutex::named("ledger", ());
+///
+/// {
+///     let _accounts = ACCOUNTS.lock().unwrap();
+///     let _ledger = LEDGER.lock().unwrap();
+/// }
+///
+/// assert_lock_order_violation!({
+///     let _ledger = LEDGER.lock().unwrap();
+///     let _accounts = ACCOUNTS.lock().unwrap();
+/// });
+/// ```
+#[macro_export]
+macro_rules! assert_lock_order_violation {
+    ($stmt:expr$(, $($arg:tt)*)?) => {{
+        let call_site = $crate::__call_site!("assert_lock_order_violation", $stmt);
+        let mark = $crate::__private::lock_order_mark();
+        let value = $stmt;
+        if $crate::__private::lock_order_violations_since(mark).is_empty() {
+            call_site.fail(
+                format_args!("caused no lock order violation."),
+                $crate::__message!($($($arg)*)?),
+            )
+        }
+        value
+    }};
+}
+
+/// Asserts that `$stmt` causes no [lock-order violation](`sync::lock_order_violations`) among [tracked locks](`sync`).
+///
+/// This is the counterpart of [`assert_lock_order_violation!`], see there for details.
+///
+/// Evaluates to the value of `$stmt`.
+///
+/// # Panics
+///
+/// Iff a violation occurred while `$stmt` ran.
+/// The failure message then lists each offending acquisition along with the earlier ones it inverts, and where they happened.
+///
+/// # Example
+///
+/// ```rust
+/// # use assert_panic::assert_panic;
+/// use assert_deadlock::{assert_no_lock_order_violation, sync::Mutex};
+///
+/// static ACCOUNTS: Mutex<()> = Mutex::named("accounts", ());
+/// static LEDGER: Mutex<()> = Mutex::named("ledger", ());
+///
+/// assert_no_lock_order_violation!({
+///     let _accounts = ACCOUNTS.lock().unwrap();
+///     let _ledger = LEDGER.lock().unwrap();
+/// });
+///
+/// assert_panic!(
+///     assert_no_lock_order_violation!({
+///         let _ledger = LEDGER.lock().unwrap();
+///         let _accounts = ACCOUNTS.lock().unwrap();
+///     }),
+///     String,
+///     contains "caused 1 lock order violation(s):\n  thread 'main' acquired `accounts` (src/lib.rs:",
+/// );
+///
+/// // The established order is still fine afterwards.
+/// assert_no_lock_order_violation!({
+///     let _accounts = ACCOUNTS.lock().unwrap();
+///     let _ledger = LEDGER.lock().unwrap();
+/// });
+/// ```
+#[macro_export]
+macro_rules! assert_no_lock_order_violation {
+    ($stmt:expr$(, $($arg:tt)*)?) => {{
+        let call_site = $crate::__call_site!("assert_no_lock_order_violation", $stmt);
+        let mark = $crate::__private::lock_order_mark();
+        let value = $stmt;
+        let violations = $crate::__private::lock_order_violations_since(mark);
+        if !violations.is_empty() {
+            call_site.fail(
+                format_args!("{}", $crate::__private::lock_order_problems(&violations)),
+                $crate::__message!($($($arg)*)?),
+            )
+        }
+        value
     }};
 }
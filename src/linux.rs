@@ -0,0 +1,217 @@
+//! Linux-specific thread introspection through `/proc`.
+
+use crate::ThreadState;
+use std::{
+    backtrace::Backtrace,
+    convert::{TryFrom, TryInto},
+    fs, io, mem,
+    ptr::{self, null_mut},
+    sync::{
+        atomic::{AtomicPtr, AtomicU64, Ordering},
+        Mutex, Once, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A kernel thread ID.
+pub type Tid = libc::pid_t;
+
+/// The calling thread's kernel thread ID.
+#[must_use]
+pub fn gettid() -> Tid {
+    // SAFETY: Always successful.
+    unsafe { libc::gettid() }
+}
+
+/// Reads the current [`ThreadState`] of the thread `tid` in this process.
+///
+/// # Errors
+///
+/// Iff the thread's `stat` can't be read or parsed, usually because the thread exited.
+pub fn sample(tid: Tid) -> io::Result<ThreadState> {
+    sample_task(&format!("/proc/self/task/{tid}"))
+}
+
+/// Reads the current [`ThreadState`] of the main thread of the child process `pid`.
+///
+/// # Errors
+///
+/// Iff the thread's `stat` can't be read or parsed, usually because the process exited.
+pub fn sample_child(pid: libc::pid_t) -> io::Result<ThreadState> {
+    sample_task(&format!("/proc/{pid}/task/{pid}"))
+}
+
+/// Reads a [`ThreadState`] from the `/proc` task directory `dir`.
+fn sample_task(dir: &str) -> io::Result<ThreadState> {
+    let stat = fs::read_to_string(format!("{dir}/stat"))?;
+    // The command name in parentheses may itself contain spaces and parentheses.
+    let state = stat
+        .rfind(')')
+        .and_then(|end| stat[end + 1..].split_whitespace().next())
+        .and_then(|state| state.chars().next())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed stat"))?;
+
+    let syscall = fs::read_to_string(format!("{dir}/syscall")).ok();
+    let mut syscall = syscall.as_deref().unwrap_or_default().split_whitespace();
+    let syscall_args = syscall
+        .clone()
+        .skip(1)
+        .map(parse_hex)
+        .collect::<Option<Vec<_>>>();
+    let syscall = syscall.next().and_then(|syscall| syscall.parse().ok());
+    let syscall_args = syscall_args.and_then(|args| args.get(..6)?.try_into().ok());
+
+    // `schedstat`'s first field is the precise on-CPU time in nanoseconds.
+    // Fall back to `stat`'s `utime` and `stime`, which are in clock ticks.
+    let cpu_time = fs::read_to_string(format!("{dir}/schedstat"))
+        .ok()
+        .and_then(|schedstat| schedstat.split_whitespace().next()?.parse().ok())
+        .map(Duration::from_nanos)
+        .or_else(|| {
+            let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace().skip(11);
+            let utime: u64 = fields.next()?.parse().ok()?;
+            let stime: u64 = fields.next()?.parse().ok()?;
+            // SAFETY: Always safe to call.
+            let ticks_per_second =
+                u64::try_from(unsafe { libc::sysconf(libc::_SC_CLK_TCK) }).ok()?;
+            Some(Duration::from_nanos(
+                (utime + stime)
+                    .checked_mul(1_000_000_000)?
+                    .checked_div(ticks_per_second)?,
+            ))
+        });
+
+    let wchan = fs::read_to_string(format!("{dir}/wchan"))
+        .ok()
+        .filter(|wchan| !matches!(wchan.trim(), "" | "0"))
+        .map(|wchan| wchan.trim().to_string());
+
+    Ok(ThreadState {
+        state,
+        syscall,
+        syscall_args,
+        wchan,
+        cpu_time,
+    })
+}
+
+/// Parses a `0x`-prefixed hexadecimal number.
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text.strip_prefix("0x")?, 16).ok()
+}
+
+/// Names some common blocking system calls.
+#[must_use]
+pub fn syscall_name(syscall: i64) -> Option<&'static str> {
+    Some(match syscall {
+        libc::SYS_futex => "futex",
+        libc::SYS_nanosleep => "nanosleep",
+        libc::SYS_clock_nanosleep => "clock_nanosleep",
+        libc::SYS_ppoll => "ppoll",
+        libc::SYS_pselect6 => "pselect6",
+        libc::SYS_epoll_pwait => "epoll_pwait",
+        libc::SYS_read => "read",
+        libc::SYS_recvfrom => "recvfrom",
+        libc::SYS_recvmsg => "recvmsg",
+        libc::SYS_accept4 => "accept4",
+        libc::SYS_wait4 => "wait4",
+        libc::SYS_waitid => "waitid",
+        libc::SYS_rt_sigtimedwait => "rt_sigtimedwait",
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_poll => "poll",
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_select => "select",
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_epoll_wait => "epoll_wait",
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_accept => "accept",
+        #[cfg(target_arch = "x86_64")]
+        libc::SYS_pause => "pause",
+        _ => return None,
+    })
+}
+
+/// How long [`backtrace`] waits for the signalled thread to respond.
+const BACKTRACE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The ID of the latest [`backtrace`] request.
+static BACKTRACE_REQUEST: AtomicU64 = AtomicU64::new(0);
+/// The signal handler's reply, along with the request ID it answers.
+static BACKTRACE_REPLY: AtomicPtr<(u64, Backtrace)> = AtomicPtr::new(null_mut());
+
+/// The signal that asks a thread for its [`Backtrace`].
+fn backtrace_signal() -> libc::c_int {
+    libc::SIGRTMIN()
+}
+
+/// Records the interrupted thread's stack as reply to the current [`backtrace`] request.
+///
+/// This allocates and takes the standard library's backtrace lock, which isn't async-signal-safe.
+/// It's only sent to threads that were just sampled as blocked in a system call, which makes that less likely to matter,
+/// but doesn't rule it out: A thread may sleep on a futex inside libc or `std`, e.g. while holding the allocator's lock.
+extern "C" fn on_backtrace_signal(_signal: libc::c_int) {
+    let request = BACKTRACE_REQUEST.load(Ordering::SeqCst);
+    let reply = Box::into_raw(Box::new((request, Backtrace::force_capture())));
+    if BACKTRACE_REPLY
+        .compare_exchange(null_mut(), reply, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        // SAFETY: Not shared.
+        drop(unsafe { Box::from_raw(reply) });
+    }
+}
+
+/// Takes the signal handler's current reply, if any.
+fn take_backtrace_reply() -> Option<Box<(u64, Backtrace)>> {
+    let reply = BACKTRACE_REPLY.swap(null_mut(), Ordering::SeqCst);
+    // SAFETY: Only ever set from `Box::into_raw`, and taken exactly once.
+    (!reply.is_null()).then(|| unsafe { Box::from_raw(reply) })
+}
+
+/// Captures the current stack of the thread `tid` in this process,
+/// by interrupting it with a real-time signal whose handler records a [`Backtrace`].
+///
+/// The thread must not exit while this runs, and should be blocked in a system call, as the handler isn't async-signal-safe.
+/// Some blocking system calls are restarted after the handler returns, but others fail with `EINTR`.
+///
+/// Returns [`None`] iff the signal can't be sent, or the thread doesn't respond within [`BACKTRACE_TIMEOUT`],
+/// e.g. because it's in uninterruptible sleep or blocks the signal.
+#[must_use]
+pub fn backtrace(tid: Tid) -> Option<Backtrace> {
+    static INSTALL: Once = Once::new();
+    static REQUESTING: Mutex<()> = Mutex::new(());
+
+    INSTALL.call_once(|| {
+        // SAFETY: Installs a valid handler with an empty mask.
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            #[allow(clippy::as_conversions)] // The handler is passed as address.
+            {
+                action.sa_sigaction = on_backtrace_signal as extern "C" fn(libc::c_int) as usize;
+            }
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(ptr::addr_of_mut!(action.sa_mask));
+            libc::sigaction(backtrace_signal(), ptr::addr_of!(action), ptr::null_mut());
+        }
+    });
+
+    let _requesting = REQUESTING.lock().unwrap_or_else(PoisonError::into_inner);
+    let request = BACKTRACE_REQUEST.fetch_add(1, Ordering::SeqCst) + 1;
+    // A late reply to an earlier request that timed out.
+    drop(take_backtrace_reply());
+
+    // SAFETY: Only signals a thread of this process, with a signal that has a handler.
+    if unsafe { libc::syscall(libc::SYS_tgkill, libc::getpid(), tid, backtrace_signal()) } != 0 {
+        return None;
+    }
+
+    let deadline = Instant::now() + BACKTRACE_TIMEOUT;
+    while Instant::now() < deadline {
+        match take_backtrace_reply() {
+            Some(reply) if reply.0 == request => return Some(reply.1),
+            _ => thread::sleep(Duration::from_millis(1)),
+        }
+    }
+    None
+}
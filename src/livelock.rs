@@ -0,0 +1,256 @@
+//! Non-panicking livelock checks.
+
+#[cfg(target_os = "linux")]
+use crate::linux;
+use crate::{
+    handle::{self, DeadlockHandle},
+    panics::WorkerPanic,
+    thread_state::ThreadState,
+    worker::Worker,
+    Config,
+};
+use std::{
+    backtrace::Backtrace,
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    thread::Thread,
+    time::{Duration, Instant},
+};
+
+/// Checks whether `stmt` livelocks, without panicking.
+///
+/// `stmt` runs on a new thread. If it is still running `config` (usually a [`Duration`](`std::time::Duration`)) after it started,
+/// and its thread consumed CPU time in both the first and the second half of that duration,
+/// the thread is considered livelocked.
+///
+/// This is the counterpart of [`check_deadlock`](`crate::check_deadlock`) for spinlocks and endless retry loops,
+/// and the building block of [`assert_livelock!`](`crate::assert_livelock!`).
+///
+/// Per-thread CPU time is currently only available on Linux, where it's read from `/proc/self/task/<tid>/schedstat`.
+/// Elsewhere, this always fails with [`LivelockCheckError::Unsupported`], without running `stmt`.
+///
+/// # Errors
+///
+/// Iff `stmt` did not livelock (or that can't be determined), see [`LivelockCheckError`].
+///
+/// # Panics
+///
+/// Iff the worker thread can't be spawned, or the [leaked threads limit](`crate::set_leaked_threads_limit`) is reached.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{sync::{Arc, atomic::{AtomicBool, Ordering}}, time::Duration};
+/// use assert_deadlock::{check_livelock, LivelockCheckError};
+///
+/// # #[cfg(target_os = "linux")] {
+/// let stop = Arc::new(AtomicBool::new(false));
+/// let livelocked = check_livelock({
+///     let stop = Arc::clone(&stop);
+///     move || while !stop.load(Ordering::Relaxed) {}
+/// }, Duration::from_millis(100)).unwrap();
+///
+/// stop.store(true, Ordering::Relaxed);
+/// livelocked.into_handle().join_within(Duration::from_secs(1));
+///
+/// assert!(matches!(
+///     check_livelock(|| std::thread::park(), Duration::from_millis(100)),
+///     Err(LivelockCheckError::Idle(..)),
+/// ));
+/// # }
+/// ```
+#[track_caller]
+pub fn check_livelock<T: Send + 'static>(
+    stmt: impl FnOnce() -> T + Send + 'static,
+    config: impl Into<Config>,
+) -> Result<Livelocked<T>, LivelockCheckError<T>> {
+    check_livelock_captured(stmt, config).0
+}
+
+/// Like [`check_livelock`], but also returns what `stmt` wrote to its [`worker_output`](`crate::worker_output`) until the check ended.
+///
+/// This is used by [`assert_livelock!`](`crate::assert_livelock!`).
+#[doc(hidden)]
+#[track_caller]
+pub fn check_livelock_captured<T: Send + 'static>(
+    stmt: impl FnOnce() -> T + Send + 'static,
+    config: impl Into<Config>,
+) -> (Result<Livelocked<T>, LivelockCheckError<T>>, Vec<u8>) {
+    if !cpu_time_supported() {
+        // Don't leave a statement spinning that can't be checked.
+        return (Err(LivelockCheckError::Unsupported), Vec::new());
+    }
+    let config = config.into();
+    let worker = Worker::spawn(Box::new(stmt), &config);
+    let output = worker.output().clone();
+    let result = observe(worker, &config);
+    (result, output.take())
+}
+
+/// Observes `worker`'s statement for CPU time use as configured.
+fn observe<T>(worker: Worker<T>, config: &Config) -> Result<Livelocked<T>, LivelockCheckError<T>> {
+    let started = worker
+        .start(config.start_timeout())
+        .ok_or(LivelockCheckError::NotStarted)?;
+
+    let mut previous = cpu_time(&worker)?;
+    let consumed_from = previous.1;
+    for deadline in [started + config.duration() / 2, started + config.duration()] {
+        if let Some(result) = worker.wait_until(deadline) {
+            return Err(completed(result));
+        }
+        let (state, cpu_time) = cpu_time(&worker)?;
+        if cpu_time <= previous.1 {
+            // Maybe it finished in the meantime.
+            return Err(worker.wait_until(Instant::now()).map_or_else(
+                || LivelockCheckError::Idle(Box::new(state), worker.backtrace()),
+                completed,
+            ));
+        }
+        previous = (state, cpu_time);
+    }
+
+    let (thread_state, cpu_time) = previous;
+    Ok(Livelocked {
+        worker,
+        started,
+        thread_state,
+        cpu_time: cpu_time.saturating_sub(consumed_from),
+    })
+}
+
+/// Whether per-thread CPU time is available, as probed on the calling thread.
+fn cpu_time_supported() -> bool {
+    #[cfg(target_os = "linux")]
+    return linux::sample(linux::gettid()).is_ok_and(|state| state.cpu_time.is_some());
+    #[cfg(not(target_os = "linux"))]
+    return false;
+}
+
+/// Samples the worker thread's state along with its CPU time.
+fn cpu_time<T>(worker: &Worker<T>) -> Result<(ThreadState, Duration), LivelockCheckError<T>> {
+    let state = worker.sample().ok_or(LivelockCheckError::Unsupported)?;
+    let cpu_time = state.cpu_time.ok_or(LivelockCheckError::Unsupported)?;
+    Ok((state, cpu_time))
+}
+
+/// Maps the result of a statement that completed to the matching [`LivelockCheckError`].
+fn completed<T>(result: Result<T, WorkerPanic>) -> LivelockCheckError<T> {
+    match result {
+        Ok(value) => LivelockCheckError::Returned(value),
+        Err(panic) => LivelockCheckError::Panicked(panic),
+    }
+}
+
+/// A statement that livelocked, as found by [`check_livelock`].
+///
+/// Dropping this abandons the still-running thread.
+pub struct Livelocked<T> {
+    /// The still-running worker.
+    worker: Worker<T>,
+    /// When the statement started running.
+    started: Instant,
+    /// The last sampled state.
+    thread_state: ThreadState,
+    /// The CPU time consumed while observed.
+    cpu_time: Duration,
+}
+
+impl<T> Livelocked<T> {
+    /// The last [`ThreadState`] sampled to measure CPU time.
+    #[must_use]
+    pub fn thread_state(&self) -> &ThreadState {
+        &self.thread_state
+    }
+
+    /// How much CPU time the thread consumed while it was observed.
+    #[must_use]
+    pub fn cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+
+    /// When the statement started running.
+    #[must_use]
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// The livelocked thread.
+    #[must_use]
+    pub fn thread(&self) -> &Thread {
+        self.worker.thread()
+    }
+
+    /// Captures where the livelocked thread is spinning right now.
+    ///
+    /// See [`Deadlocked::backtrace`](`crate::Deadlocked::backtrace`) for how this works and its caveats.
+    /// A busy thread isn't interrupted, as it may be inside the allocator, so this is usually [`None`]
+    /// unless the thread happens to be blocked in a system call at the moment.
+    #[must_use]
+    pub fn backtrace(&self) -> Option<Backtrace> {
+        self.worker.backtrace()
+    }
+
+    /// Takes what the statement wrote to its [`worker_output`](`crate::worker_output`) since the check ended (or this was last called).
+    #[must_use]
+    pub fn take_output(&self) -> Vec<u8> {
+        self.worker.output().take()
+    }
+
+    /// Converts this into a [`DeadlockHandle`], which can later join the worker thread.
+    #[must_use]
+    pub fn into_handle(self) -> DeadlockHandle<T> {
+        handle::new(self.worker, self.started, None)
+    }
+}
+
+impl<T> Debug for Livelocked<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Livelocked")
+            .field("thread", self.thread())
+            .field("started", &self.started)
+            .field("thread_state", &self.thread_state)
+            .field("cpu_time", &self.cpu_time)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why [`check_livelock`] found no livelock.
+#[derive(Debug)]
+pub enum LivelockCheckError<T> {
+    /// The statement returned this value in time.
+    Returned(T),
+    /// The statement panicked.
+    Panicked(WorkerPanic),
+    /// The worker thread did not start running the statement within the [start timeout](`Config::with_start_timeout`).
+    NotStarted,
+    /// The statement was still running, but stopped consuming CPU time at some point.
+    ///
+    /// This usually means that it's blocked (or deadlocked) rather than livelocked.
+    /// The thread is abandoned.
+    ///
+    /// The [`Backtrace`] shows where the thread was stuck, if it could be captured (see [`Deadlocked::backtrace`](`crate::Deadlocked::backtrace`)).
+    Idle(Box<ThreadState>, Option<Backtrace>),
+    /// Per-thread CPU time isn't available on this platform.
+    ///
+    /// The statement doesn't run in that case.
+    Unsupported,
+}
+
+impl<T> Display for LivelockCheckError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LivelockCheckError::Returned(_) => write!(f, "The statement returned."),
+            LivelockCheckError::Panicked(_) => write!(f, "The statement panicked."),
+            LivelockCheckError::NotStarted => write!(f, "The statement did not start in time."),
+            LivelockCheckError::Idle(state, _) => {
+                write!(f, "The statement stopped consuming CPU time: {state}")
+            }
+            LivelockCheckError::Unsupported => {
+                write!(f, "Per-thread CPU time is unavailable on this platform.")
+            }
+        }
+    }
+}
+
+impl<T: Debug> Error for LivelockCheckError<T> {}
@@ -0,0 +1,291 @@
+//! The global lock-class ordering graph of [tracked locks](`crate::sync`), in the spirit of the Linux kernel's lockdep.
+//!
+//! Every blocking acquisition while holding other tracked locks records that those are ordered before the new one.
+//! An acquisition that closes a cycle in this order could deadlock with the threads that established it,
+//! whether or not the timing of this run actually lets it. It's recorded as a violation, but not added to the order, so the order stays acyclic.
+
+use crate::sync::LockId;
+use std::{
+    fmt::{self, Display, Formatter},
+    panic::Location,
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread::{self, Thread},
+};
+
+/// Which locks are ordered as one in the [lock-order graph](`crate::sync::lock_order_violations`).
+///
+/// Like in lockdep, [named](`crate::sync::Mutex::named`) locks with the same name form one class,
+/// e.g. all `account` locks of a bank, so that an inversion is caught no matter which instances were involved.
+/// Each unnamed lock is its own class.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockClass {
+    /// All locks with this name.
+    Named(&'static str),
+    /// This unnamed lock.
+    Unnamed(LockId),
+}
+
+impl LockClass {
+    /// The class of the lock `id`, which is named `name`, if at all.
+    pub(crate) fn of(id: LockId, name: Option<&'static str>) -> Self {
+        name.map_or(Self::Unnamed(id), Self::Named)
+    }
+}
+
+impl Display for LockClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LockClass::Named(name) => write!(f, "`{name}`"),
+            LockClass::Unnamed(id) => write!(f, "lock {id}"),
+        }
+    }
+}
+
+/// An observed acquisition of one lock class while holding another.
+#[derive(Debug, Clone)]
+pub struct LockOrderEdge {
+    /// The held lock's class.
+    held: LockClass,
+    /// Where the held lock was acquired.
+    held_at: &'static Location<'static>,
+    /// The acquired lock's class.
+    acquired: LockClass,
+    /// Where it was acquired.
+    acquired_at: &'static Location<'static>,
+    /// The acquiring thread.
+    thread: Thread,
+}
+
+impl LockOrderEdge {
+    /// The class of the lock that was held.
+    #[must_use]
+    pub fn held(&self) -> LockClass {
+        self.held
+    }
+
+    /// Where the held lock was acquired.
+    #[must_use]
+    pub fn held_at(&self) -> &'static Location<'static> {
+        self.held_at
+    }
+
+    /// The class of the lock that was acquired while holding the other one.
+    #[must_use]
+    pub fn acquired(&self) -> LockClass {
+        self.acquired
+    }
+
+    /// Where that lock was acquired.
+    #[must_use]
+    pub fn acquired_at(&self) -> &'static Location<'static> {
+        self.acquired_at
+    }
+
+    /// The thread that acquired it.
+    #[must_use]
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+}
+
+impl Display for LockOrderEdge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread '{}' acquired {} ({}) while holding {} ({})",
+            self.thread.name().unwrap_or("<unnamed>"),
+            self.acquired,
+            self.acquired_at,
+            self.held,
+            self.held_at,
+        )
+    }
+}
+
+/// An acquisition that inverts the lock order observed before, so that it could deadlock.
+///
+/// This displays as a report like
+/// "thread 'b' acquired `accounts` (src/bank.rs:21:5) while holding `ledger` (src/bank.rs:20:5),
+/// inverting: thread 'a' acquired `ledger` (src/bank.rs:11:5) while holding `accounts` (src/bank.rs:10:5)".
+#[derive(Debug, Clone)]
+pub struct LockOrderViolation {
+    /// The offending acquisition.
+    acquisition: LockOrderEdge,
+    /// The earlier acquisitions that ordered the acquired class before the held one.
+    inversion: Vec<LockOrderEdge>,
+}
+
+impl LockOrderViolation {
+    /// The acquisition that closed the cycle.
+    #[must_use]
+    pub fn acquisition(&self) -> &LockOrderEdge {
+        &self.acquisition
+    }
+
+    /// The earlier acquisitions that lead from the [acquired](`LockOrderEdge::acquired`) class back to the [held](`LockOrderEdge::held`) one, in order.
+    #[must_use]
+    pub fn inversion(&self) -> &[LockOrderEdge] {
+        &self.inversion
+    }
+}
+
+impl Display for LockOrderViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, inverting: ", self.acquisition)?;
+        for (index, edge) in self.inversion.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{edge}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The observed lock order, with the first acquisition that established each edge.
+static ORDER: Mutex<Vec<LockOrderEdge>> = Mutex::new(Vec::new());
+
+/// Every violation found so far.
+static VIOLATIONS: Mutex<Vec<LockOrderViolation>> = Mutex::new(Vec::new());
+
+/// Locks the global lock-order graph.
+fn order() -> MutexGuard<'static, Vec<LockOrderEdge>> {
+    ORDER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Locks the list of violations.
+fn violations() -> MutexGuard<'static, Vec<LockOrderViolation>> {
+    VIOLATIONS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Records that the current thread is about to block on a lock of class `acquired`, as called at `acquired_at`,
+/// while holding the locks in `held`, and records any violations of the lock order this causes.
+pub fn acquiring(
+    held: &[(LockClass, &'static Location<'static>)],
+    acquired: LockClass,
+    acquired_at: &'static Location<'static>,
+) {
+    if held.is_empty() {
+        return;
+    }
+    let thread = thread::current();
+    let mut order = order();
+    for &(held, held_at) in held {
+        if held == acquired {
+            // Nesting within a class would need annotations to tell apart from recursive locking.
+            continue;
+        }
+        let acquisition = LockOrderEdge {
+            held,
+            held_at,
+            acquired,
+            acquired_at,
+            thread: thread.clone(),
+        };
+        if let Some(inversion) = path(&order, acquired, held) {
+            let violation = LockOrderViolation {
+                acquisition,
+                inversion,
+            };
+            violations().push(violation);
+            // Not recorded, so that the order stays acyclic and the established order isn't reported in turn.
+            continue;
+        }
+        if !order
+            .iter()
+            .any(|edge| edge.held == held && edge.acquired == acquired)
+        {
+            order.push(acquisition);
+        }
+    }
+}
+
+/// Finds a path of edges in `order` from `from` to `to`.
+fn path(order: &[LockOrderEdge], from: LockClass, to: LockClass) -> Option<Vec<LockOrderEdge>> {
+    /// Depth-first search from the end of `path`, not revisiting `visited`.
+    fn search(
+        order: &[LockOrderEdge],
+        from: LockClass,
+        to: LockClass,
+        visited: &mut Vec<LockClass>,
+        path: &mut Vec<LockOrderEdge>,
+    ) -> bool {
+        if from == to {
+            return true;
+        }
+        visited.push(from);
+        for edge in order.iter().filter(|edge| edge.held == from) {
+            if visited.contains(&edge.acquired) {
+                continue;
+            }
+            path.push(edge.clone());
+            if search(order, edge.acquired, to, visited, path) {
+                return true;
+            }
+            path.pop();
+        }
+        false
+    }
+
+    let mut path = Vec::new();
+    search(order, from, to, &mut Vec::new(), &mut path).then_some(path)
+}
+
+/// Lists every lock-order violation among [tracked locks](`crate::sync`) so far, in the order they occurred.
+///
+/// Each of these is an acquisition that took a lock while holding another,
+/// when the reverse order had been observed before (possibly through other locks, and on any thread).
+/// Threads doing both could deadlock, even if they didn't in this run.
+/// Violations are only recorded here, as some are expected (see [`assert_lock_order_violation!`](`crate::assert_lock_order_violation!`)).
+///
+/// See [`assert_lock_order_violation!`](`crate::assert_lock_order_violation!`) and [`assert_no_lock_order_violation!`](`crate::assert_no_lock_order_violation!`).
+///
+/// # Example
+///
+/// ```rust
+/// use assert_deadlock::sync::{lock_order_violations, Mutex};
+///
+/// static ACCOUNTS: Mutex<()> = Mutex::named("accounts", ());
+/// static LEDGER: Mutex<()> = Mutex::named("ledger", ());
+///
+/// {
+///     let _accounts = ACCOUNTS.lock().unwrap();
+///     let _ledger = LEDGER.lock().unwrap();
+/// }
+/// assert!(lock_order_violations().is_empty());
+///
+/// {
+///     let _ledger = LEDGER.lock().unwrap();
+///     let _accounts = ACCOUNTS.lock().unwrap();
+/// }
+/// let violations = lock_order_violations();
+/// assert_eq!(violations.len(), 1);
+/// assert!(violations[0]
+///     .to_string()
+///     .contains("acquired `accounts` (src/lock_order.rs:"));
+///
+/// // The established order is still fine.
+/// {
+///     let _accounts = ACCOUNTS.lock().unwrap();
+///     let _ledger = LEDGER.lock().unwrap();
+/// }
+/// assert_eq!(lock_order_violations().len(), 1);
+/// ```
+#[must_use]
+pub fn lock_order_violations() -> Vec<LockOrderViolation> {
+    violations().clone()
+}
+
+/// The number of violations so far, to tell apart later ones.
+#[doc(hidden)]
+#[must_use]
+pub fn lock_order_mark() -> usize {
+    violations().len()
+}
+
+/// The violations since `mark` was taken with [`lock_order_mark`].
+#[doc(hidden)]
+#[must_use]
+pub fn lock_order_violations_since(mark: usize) -> Vec<LockOrderViolation> {
+    violations().get(mark..).unwrap_or_default().to_vec()
+}
@@ -0,0 +1,110 @@
+//! Per-worker output capture for statements that run on a worker thread.
+
+use std::{
+    cell::RefCell,
+    io::{self, stderr, Write},
+    mem,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// Where a worker thread's [`worker_output`] goes.
+#[derive(Debug, Clone, Default)]
+pub struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Sink {
+    /// Routes the current thread's [`worker_output`] into this sink.
+    pub fn install(&self) {
+        CURRENT.with(|current| *current.borrow_mut() = Some(self.clone()));
+    }
+
+    /// Takes what was written so far.
+    pub fn take(&self) -> Vec<u8> {
+        mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+thread_local! {
+    /// The current thread's sink, if it's a worker thread.
+    static CURRENT: RefCell<Option<Sink>> = const { RefCell::new(None) };
+}
+
+/// Returns a writer for output of a statement under test.
+///
+/// On a worker thread of this crate's assertions, like [`assert_deadlock!`](`crate::assert_deadlock!`),
+/// what's written here is captured rather than interleaved with the output of other tests,
+/// then included in a failure report, or discarded if the assertion holds.
+/// The non-panicking checks return it through [`check_deadlock_captured`](`crate::check_deadlock_captured`)
+/// and [`Deadlocked::take_output`](`crate::Deadlocked::take_output`) instead.
+/// Elsewhere, it's written to [standard error](`std::io::stderr`), which includes [isolated](`crate::check_deadlock_isolated`) statements, whose standard error is captured anyway.
+///
+/// [`print!`] and [`eprint!`] on the worker thread can't be captured like this on stable Rust,
+/// and redirecting the process's file descriptors would also capture concurrently running tests.
+/// Use this instead for diagnostics of the statement itself.
+///
+/// # Example
+///
+/// ```rust
+/// # use {
+/// #     assert_panic::assert_panic,
+/// #     std::{io::Write, sync::Mutex, time::Duration},
+/// # };
+/// use assert_deadlock::{assert_deadlock, assert_no_deadlock, worker_output};
+///
+/// assert_panic!(
+///     {
+///         assert_deadlock!(
+///             writeln!(worker_output(), "Not blocked!").unwrap(),
+///             Duration::from_secs(1),
+///         );
+///     },
+///     String,
+///     contains "returned within 1s.\n\ncaptured output:\nNot blocked!",
+/// );
+///
+/// static MUTEX: Mutex<()> = Mutex::new(());
+///
+/// let guard = MUTEX.lock();
+/// assert_panic!(
+///     {
+///         assert_no_deadlock!(
+///             {
+///                 writeln!(worker_output(), "Locking...").unwrap();
+///                 drop(MUTEX.lock());
+///             },
+///             Duration::from_millis(100),
+///         );
+///     },
+///     String,
+///     contains "\n\ncaptured output:\nLocking...",
+/// );
+/// ```
+#[must_use]
+pub fn worker_output() -> WorkerOutput {
+    WorkerOutput(CURRENT.with(|current| current.borrow().clone()))
+}
+
+/// The writer returned by [`worker_output`].
+#[derive(Debug)]
+pub struct WorkerOutput(Option<Sink>);
+
+impl Write for WorkerOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &self.0 {
+            Some(sink) => {
+                sink.0
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            None => stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &self.0 {
+            Some(_) => Ok(()),
+            None => stderr().flush(),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Capturing panics on worker threads, so that they can be reported faithfully on the calling thread.
+
+use std::{
+    any::Any,
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::{Cell, RefCell},
+    fmt::{self, Debug, Display, Formatter},
+    panic::{self, resume_unwind, PanicHookInfo},
+    sync::Once,
+    thread,
+};
+
+thread_local! {
+    /// Whether panics on this thread are captured instead of reported by the previous panic hook.
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+    /// The details of the last captured panic on this thread.
+    static CAPTURED: RefCell<Option<Captured>> = const { RefCell::new(None) };
+}
+
+/// Details of a panic that are only available to the panic hook.
+struct Captured {
+    /// Where the panic happened.
+    location: Option<String>,
+    /// The stack at the time of the panic.
+    backtrace: Backtrace,
+}
+
+/// Installs the capturing panic hook, if that didn't happen yet.
+fn install_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info: &PanicHookInfo<'_>| {
+            if CAPTURING.try_with(Cell::get).unwrap_or(false) {
+                let captured = Captured {
+                    location: info.location().map(ToString::to_string),
+                    backtrace: Backtrace::capture(),
+                };
+                let _ = CAPTURED.try_with(|slot| slot.replace(Some(captured)));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+/// Runs `f` with panics captured instead of reported.
+///
+/// The previous panic hook is skipped for panics inside `f`, so that they are only reported once, on the calling thread.
+pub fn catch<T>(f: impl FnOnce() -> T) -> Result<T, WorkerPanic> {
+    install_hook();
+    CAPTURING.with(|capturing| capturing.set(true));
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(f));
+    CAPTURING.with(|capturing| capturing.set(false));
+    result.map_err(|payload| {
+        let captured = CAPTURED.with(RefCell::take);
+        let (location, backtrace) = captured.map_or((None, None), |captured| {
+            (Some(captured.location), Some(captured.backtrace))
+        });
+        WorkerPanic {
+            payload,
+            thread: thread::current().name().map(ToString::to_string),
+            location: location.flatten(),
+            backtrace,
+        }
+    })
+}
+
+/// Recreates a [`WorkerPanic`] from its parts, e.g. as reported by an [isolated](`crate::check_deadlock_isolated`) child process.
+///
+/// The payload becomes the `message`, if any.
+#[cfg(target_os = "linux")]
+pub(crate) fn from_parts(
+    message: Option<String>,
+    thread: Option<String>,
+    location: Option<String>,
+) -> WorkerPanic {
+    WorkerPanic {
+        payload: match message {
+            Some(message) => Box::new(message),
+            None => Box::new(()),
+        },
+        thread,
+        location,
+        backtrace: None,
+    }
+}
+
+/// A panic that happened on a worker thread.
+///
+/// [`Display`] formats this similarly to the default panic hook.
+pub struct WorkerPanic {
+    /// The original payload.
+    payload: Box<dyn Any + Send + 'static>,
+    /// The name of the thread that panicked.
+    thread: Option<String>,
+    /// Where the panic happened.
+    location: Option<String>,
+    /// The worker's stack at the time of the panic, if captured.
+    backtrace: Option<Backtrace>,
+}
+
+impl WorkerPanic {
+    /// The original panic payload.
+    #[must_use]
+    pub fn payload(&self) -> &(dyn Any + Send + 'static) {
+        &*self.payload
+    }
+
+    /// Converts this into the original panic payload.
+    #[must_use]
+    pub fn into_payload(self) -> Box<dyn Any + Send + 'static> {
+        self.payload
+    }
+
+    /// The panic message, iff the payload is a [`&str`](`str`) or [`String`].
+    #[must_use]
+    pub fn message(&self) -> Option<&str> {
+        self.payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| self.payload.downcast_ref::<String>().map(String::as_str))
+    }
+
+    /// Where the panic happened, as `file:line:column`.
+    #[must_use]
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    /// The worker's stack at the time of the panic.
+    ///
+    /// Like with the default panic hook, this is only captured if enabled through `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE`.
+    #[must_use]
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_ref()
+    }
+
+    /// Reports this panic to standard error and then resumes unwinding with the original payload on the current thread.
+    ///
+    /// Since the payload is unchanged, its type can still be matched on further up the stack.
+    pub fn resume(self) -> ! {
+        eprintln!("{self}");
+        resume_unwind(self.payload)
+    }
+}
+
+impl Display for WorkerPanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "thread '{}' panicked",
+            self.thread.as_deref().unwrap_or("<unnamed>")
+        )?;
+        if let Some(location) = &self.location {
+            write!(f, " at {location}")?;
+        }
+        write!(f, ":\n{}", self.message().unwrap_or("Box<dyn Any>"))?;
+        if let Some(backtrace) = &self.backtrace {
+            if backtrace.status() == BacktraceStatus::Captured {
+                write!(f, "\nstack backtrace:\n{backtrace}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Debug for WorkerPanic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkerPanic")
+            .field("message", &self.message())
+            .field("thread", &self.thread)
+            .field("location", &self.location)
+            .field("backtrace", &self.backtrace)
+            .finish_non_exhaustive()
+    }
+}
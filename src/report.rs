@@ -0,0 +1,287 @@
+//! Failure messages for this crate's assertions.
+
+use crate::{
+    sync::{LockOrderViolation, TrackedLock, WaitCycle},
+    wait_for::{self, LockLabel},
+    Config, DeadlockCheckError, Deadlocked,
+};
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    fmt::{self, Arguments, Display, Formatter, Write},
+    thread::Thread,
+};
+
+/// Where and on what an assertion macro was invoked.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct CallSite {
+    /// The macro's name, without `!`.
+    pub macro_name: &'static str,
+    /// The stringified statement.
+    pub stmt: &'static str,
+    /// The [`file!()`].
+    pub file: &'static str,
+    /// The [`line!()`].
+    pub line: u32,
+    /// The [`column!()`].
+    pub column: u32,
+}
+
+impl CallSite {
+    /// Panics with [`report`](`CallSite::report`)`(problem, message)`.
+    ///
+    /// # Panics
+    ///
+    /// Always.
+    #[track_caller]
+    pub fn fail(&self, problem: Arguments<'_>, message: Option<Arguments<'_>>) -> ! {
+        panic!("{}", self.report(problem, message))
+    }
+
+    /// Describes `problem` with the assertion, preceded by the user-supplied `message` (if any).
+    #[must_use]
+    pub fn report(&self, problem: Arguments<'_>, message: Option<Arguments<'_>>) -> String {
+        let mut report = String::new();
+        if let Some(message) = message {
+            writeln!(report, "{message}").expect("infallible");
+        }
+        write!(
+            report,
+            "{}! failed at {}:{}:{}: `{}` {}",
+            self.macro_name, self.file, self.line, self.column, self.stmt, problem,
+        )
+        .expect("infallible");
+        report
+    }
+
+    /// Writes the wait-for `cycle` that proved a deadlock to standard error, if there is one.
+    ///
+    /// The test harness shows this only for failing tests or with `--nocapture`.
+    pub fn note_cycle(&self, cycle: Option<&WaitCycle>) {
+        if let Some(cycle) = cycle {
+            eprintln!(
+                "{}! at {}:{}:{}: `{}` deadlocked in a wait-for cycle: {}",
+                self.macro_name, self.file, self.line, self.column, self.stmt, cycle,
+            );
+        }
+    }
+}
+
+/// Appends a worker thread's stack to a failure message, if it was captured.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct WorkerStack<'a>(pub Option<&'a Backtrace>);
+
+impl Display for WorkerStack<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(backtrace) if backtrace.status() == BacktraceStatus::Captured => {
+                write!(f, "\n\nworker thread stack backtrace:\n{backtrace}")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Appends the wait-for cycle a worker thread is stuck in to a failure message, if one was found.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct WaitForCycle<'a>(pub Option<&'a WaitCycle>);
+
+impl Display for WaitForCycle<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(cycle) => write!(f, "\n\nwait-for cycle: {cycle}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Appends a statement's captured output to a failure message, if there is any.
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct CapturedOutput<'a>(pub &'a [u8]);
+
+impl CapturedOutput<'_> {
+    /// Writes the captured output to standard error, if there is any.
+    ///
+    /// This is used before resuming a statement's panic.
+    pub fn eprint(&self) {
+        if !self.0.is_empty() {
+            eprintln!(
+                "captured output:\n{}",
+                String::from_utf8_lossy(self.0).trim_end()
+            );
+        }
+    }
+}
+
+impl Display for CapturedOutput<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            write!(
+                f,
+                "\n\ncaptured output:\n{}",
+                String::from_utf8_lossy(self.0).trim_end()
+            )
+        }
+    }
+}
+
+/// A [`CheckResult`], split into its [`DeadlockCheckError`]-based result and the captured output.
+pub(crate) type Split<D, T> = (Result<D, DeadlockCheckError<T>>, Vec<u8>);
+
+/// The result of a deadlock check, split into a [`DeadlockCheckError`]-based result and the statement's captured output.
+///
+/// This lets [`assert_deadlock!`](`crate::assert_deadlock!`) handle regular and isolated checks alike.
+#[doc(hidden)]
+pub trait CheckResult {
+    /// What a successful check returns.
+    type Deadlocked;
+    /// What the statement returns.
+    type Value;
+
+    /// Splits off the captured output.
+    fn split(self) -> Split<Self::Deadlocked, Self::Value>;
+}
+
+impl<T> CheckResult for Split<Deadlocked<T>, T> {
+    type Deadlocked = Deadlocked<T>;
+    type Value = T;
+
+    fn split(self) -> Split<Self::Deadlocked, Self::Value> {
+        self
+    }
+}
+
+/// The wait-for cycle that proved a deadlock, for [`assert_deadlock!`](`crate::assert_deadlock!`)'s [note](`CallSite::note_cycle`).
+#[doc(hidden)]
+pub trait ProvenCycle {
+    /// The cycle of [tracked locks](`crate::sync`) the statement is stuck in, if one was found.
+    fn proven_cycle(&self) -> Option<&WaitCycle>;
+}
+
+impl<T> ProvenCycle for Deadlocked<T> {
+    fn proven_cycle(&self) -> Option<&WaitCycle> {
+        self.cycle()
+    }
+}
+
+/// Describes which of several statements (as stringified in `stmts`) didn't deadlock, along with their captured output,
+/// or returns [`None`] if all did.
+///
+/// This is used by [`assert_deadlock_all!`](`crate::assert_deadlock_all!`).
+#[doc(hidden)]
+#[must_use]
+pub fn deadlock_all_problems<T>(
+    stmts: &[&str],
+    results: &[Split<Deadlocked<T>, T>],
+    config: &Config,
+) -> Option<String> {
+    if results.iter().all(|(result, _)| result.is_ok()) {
+        return None;
+    }
+    let mut problems = format!("not all blocked after {:?}:", config.duration());
+    for (stmt, (result, output)) in stmts.iter().zip(results) {
+        write!(problems, "\n  `{stmt}` ").expect("infallible");
+        match result {
+            Ok(_) => write!(problems, "still blocked."),
+            Err(DeadlockCheckError::Returned(_)) => write!(problems, "returned."),
+            Err(DeadlockCheckError::Panicked(panic)) => {
+                write!(
+                    problems,
+                    "panicked: {}",
+                    panic.to_string().replace('\n', "\n    ")
+                )
+            }
+            Err(DeadlockCheckError::NotStarted) => {
+                write!(
+                    problems,
+                    "did not start within {:?}.",
+                    config.start_timeout()
+                )
+            }
+            Err(DeadlockCheckError::NotBlocked(state, backtrace)) => write!(
+                problems,
+                "still running, not blocked: {}{}",
+                state,
+                WorkerStack(backtrace.as_ref()),
+            ),
+        }
+        .expect("infallible");
+        if !output.is_empty() {
+            write!(
+                problems,
+                "\n    captured output:\n      {}",
+                String::from_utf8_lossy(output)
+                    .trim_end()
+                    .replace('\n', "\n      "),
+            )
+            .expect("infallible");
+        }
+    }
+    Some(problems)
+}
+
+/// Describes how `thread` isn't blocked on `expected`, or returns [`None`] if it is.
+///
+/// This is used by [`assert_deadlock_on!`](`crate::assert_deadlock_on!`).
+#[doc(hidden)]
+#[must_use]
+pub fn deadlock_on_problem(thread: &Thread, expected: &impl TrackedLock) -> Option<String> {
+    let expected = LockLabel(expected.id(), expected.name());
+    match wait_for::waiting_on(thread.id()) {
+        Some((lock, _, _)) if lock == expected.0 => None,
+        Some((lock, name, location)) => Some(format!(
+            "blocked on {} ({}) instead of {}.",
+            LockLabel(lock, name),
+            location,
+            expected,
+        )),
+        None => Some(format!(
+            "blocked, but not on a tracked lock, instead of {expected}."
+        )),
+    }
+}
+
+/// Lists lock-order `violations` for a failure message.
+///
+/// This is used by [`assert_no_lock_order_violation!`](`crate::assert_no_lock_order_violation!`).
+#[doc(hidden)]
+#[must_use]
+pub fn lock_order_problems(violations: &[LockOrderViolation]) -> String {
+    let mut problems = format!("caused {} lock order violation(s):", violations.len());
+    for violation in violations {
+        write!(problems, "\n  {violation}").expect("infallible");
+    }
+    problems
+}
+
+/// Creates a [`CallSite`](`crate::__private::CallSite`) for the current macro invocation.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __call_site {
+    ($macro_name:literal, $stmt:expr) => {
+        $crate::__private::CallSite {
+            macro_name: $macro_name,
+            stmt: stringify!($stmt),
+            file: file!(),
+            line: line!(),
+            column: column!(),
+        }
+    };
+}
+
+/// Turns optional trailing [`format!`] arguments into an [`Option<Arguments>`](`std::fmt::Arguments`).
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __message {
+    () => {
+        None
+    };
+    ($($arg:tt)+) => {
+        Some(format_args!($($arg)+))
+    };
+}
@@ -0,0 +1,698 @@
+//! Drop-in replacements for [`std::sync`]'s locks that record who holds and waits on what.
+//!
+//! While any thread is blocked on one of these, [`assert_deadlock!`](`crate::assert_deadlock!`) and [`check_deadlock`](`crate::check_deadlock`)
+//! look for a cycle of threads waiting on each other's locks.
+//! If the worker thread is part of (or waits on) one, that *proves* the deadlock,
+//! and the check succeeds right away instead of after the full duration.
+//! See [`Deadlocked::cycle`](`crate::Deadlocked::cycle`).
+//!
+//! The wrappers behave like their [`std::sync`] counterparts otherwise, including poisoning.
+//! [`RwLock`] readers are assumed not to block each other, so writer-preferring stalls are never reported as cycles.
+//!
+//! Locks can be [named](`Mutex::named`) for the [report](`WaitCycle`) that describes the cycle,
+//! and acquisitions are recorded with their call sites.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{thread, time::{Duration, Instant}};
+//! use assert_deadlock::{assert_deadlock, sync::Mutex};
+//!
+//! static ACCOUNTS: Mutex<()> = Mutex::named("accounts", ());
+//! static LEDGER: Mutex<()> = Mutex::named("ledger", ());
+//!
+//! thread::Builder::new()
+//!     .name("transfer".to_string())
+//!     .spawn(|| {
+//!         let _ledger = LEDGER.lock().unwrap();
+//!         thread::sleep(Duration::from_millis(100));
+//!         drop(ACCOUNTS.lock());
+//!     })
+//!     .unwrap();
+//! thread::sleep(Duration::from_millis(50));
+//!
+//! let start = Instant::now();
+//! let handle = assert_deadlock!(
+//!     {
+//!         let _accounts = ACCOUNTS.lock().unwrap();
+//!         drop(LEDGER.lock());
+//!     },
+//!     Duration::from_secs(10),
+//! );
+//! assert!(start.elapsed() < Duration::from_secs(10));
+//!
+//! let report = handle.cycle().unwrap().to_string();
+//! assert!(report.contains("; thread 'transfer' holds `ledger` (src/sync.rs:"));
+//! ```
+
+use crate::wait_for::{self, Access, LazyLockId};
+pub use crate::{
+    lock_order::{lock_order_violations, LockClass, LockOrderEdge, LockOrderViolation},
+    wait_for::{LockId, WaitCycle, WaitEdge},
+};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    mem::ManuallyDrop,
+    ops::{Deref, DerefMut},
+    panic::Location,
+    sync::{self, LockResult, PoisonError, TryLockError, TryLockResult},
+    time::Duration,
+};
+
+/// A [tracked](self) lock or condition variable, which [`assert_deadlock_on!`](`crate::assert_deadlock_on!`) can expect a statement to block on.
+pub trait TrackedLock {
+    /// The lock's [`LockId`] in the wait-for graph.
+    fn id(&self) -> LockId;
+
+    /// The lock's name, if it was created with one.
+    fn name(&self) -> Option<&'static str>;
+}
+
+impl<T: ?Sized> TrackedLock for Mutex<T> {
+    fn id(&self) -> LockId {
+        Mutex::id(self)
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        Mutex::name(self)
+    }
+}
+
+impl<T: ?Sized> TrackedLock for RwLock<T> {
+    fn id(&self) -> LockId {
+        RwLock::id(self)
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        RwLock::name(self)
+    }
+}
+
+impl TrackedLock for Condvar {
+    fn id(&self) -> LockId {
+        Condvar::id(self)
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        Condvar::name(self)
+    }
+}
+
+impl<L: TrackedLock + ?Sized> TrackedLock for &L {
+    fn id(&self) -> LockId {
+        (**self).id()
+    }
+
+    fn name(&self) -> Option<&'static str> {
+        (**self).name()
+    }
+}
+
+/// Converts the guard inside a [`LockResult`].
+fn map_result<G, U>(result: LockResult<G>, f: impl FnOnce(G) -> U) -> LockResult<U> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(poisoned) => Err(PoisonError::new(f(poisoned.into_inner()))),
+    }
+}
+
+/// Converts the guard inside a [`TryLockResult`].
+fn map_try_result<G, U>(result: TryLockResult<G>, f: impl FnOnce(G) -> U) -> TryLockResult<U> {
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(TryLockError::Poisoned(poisoned)) => Err(TryLockError::Poisoned(PoisonError::new(f(
+            poisoned.into_inner(),
+        )))),
+        Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+    }
+}
+
+/// Acquires a lock through `lock`, recording the wait and then the acquisition, as called at `location`.
+fn acquire<G>(
+    (id, name): (LockId, Option<&'static str>),
+    access: Access,
+    location: &'static Location<'static>,
+    lock: impl FnOnce() -> G,
+) -> G {
+    wait_for::wait(id, name, access, location);
+    let guard = lock();
+    wait_for::acquired(id, name, access, location);
+    guard
+}
+
+/// Tries to acquire a lock through `try_lock`, recording the acquisition if it succeeded, as called at `location`.
+fn try_acquire<G>(
+    (id, name): (LockId, Option<&'static str>),
+    access: Access,
+    location: &'static Location<'static>,
+    try_lock: impl FnOnce() -> TryLockResult<G>,
+) -> TryLockResult<G> {
+    let result = try_lock();
+    if !matches!(result, Err(TryLockError::WouldBlock)) {
+        wait_for::acquired(id, name, access, location);
+    }
+    result
+}
+
+/// A [`std::sync::Mutex`] that records who holds and waits on it.
+#[derive(Default)]
+pub struct Mutex<T: ?Sized> {
+    /// Identifies this lock in the wait-for graph.
+    id: LazyLockId,
+    /// Names this lock in [cycle reports](`WaitCycle`).
+    name: Option<&'static str>,
+    /// The actual lock.
+    inner: sync::Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Creates a new unlocked mutex containing `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            id: LazyLockId::new(),
+            name: None,
+            inner: sync::Mutex::new(value),
+        }
+    }
+
+    /// Creates a new unlocked mutex containing `value`, which [cycle reports](`WaitCycle`) call `name`.
+    pub const fn named(name: &'static str, value: T) -> Self {
+        Self {
+            id: LazyLockId::new(),
+            name: Some(name),
+            inner: sync::Mutex::new(value),
+        }
+    }
+
+    /// Consumes this mutex, returning the contained value.
+    ///
+    /// # Errors
+    ///
+    /// Iff the mutex is poisoned, see [`std::sync::Mutex::into_inner`].
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// This mutex's [`LockId`] in the wait-for graph.
+    #[must_use]
+    pub fn id(&self) -> LockId {
+        self.id.get()
+    }
+
+    /// This mutex's name, if it was created [with one](`Self::named`).
+    #[must_use]
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// The [`LockId`] and name to record in the wait-for graph.
+    fn tracked(&self) -> (LockId, Option<&'static str>) {
+        (self.id(), self.name)
+    }
+
+    /// Acquires this mutex, blocking the current thread until it's able to do so.
+    ///
+    /// # Errors
+    ///
+    /// Iff the mutex is poisoned, see [`std::sync::Mutex::lock`].
+    #[track_caller]
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let tracked = self.tracked();
+        map_result(
+            acquire(tracked, Access::Exclusive, Location::caller(), || {
+                self.inner.lock()
+            }),
+            |inner| MutexGuard::new(tracked, inner),
+        )
+    }
+
+    /// Attempts to acquire this mutex without blocking.
+    ///
+    /// # Errors
+    ///
+    /// Iff the mutex is locked or poisoned, see [`std::sync::Mutex::try_lock`].
+    #[track_caller]
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        let tracked = self.tracked();
+        map_try_result(
+            try_acquire(tracked, Access::Exclusive, Location::caller(), || {
+                self.inner.try_lock()
+            }),
+            |inner| MutexGuard::new(tracked, inner),
+        )
+    }
+
+    /// Whether the mutex is poisoned.
+    #[must_use]
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    /// Returns a mutable reference to the contained value, without locking.
+    ///
+    /// # Errors
+    ///
+    /// Iff the mutex is poisoned, see [`std::sync::Mutex::get_mut`].
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for Mutex<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mutex")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("inner", &&self.inner)
+            .finish()
+    }
+}
+
+/// A held [`Mutex`]. Dropping this unlocks it.
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MutexGuard<'a, T: ?Sized> {
+    /// The mutex's ID and name, for re-acquiring it after [`Condvar::wait`].
+    mutex: (LockId, Option<&'static str>),
+    /// The actual guard. Only taken out in [`Drop`] and [`MutexGuard::into_inner`].
+    inner: ManuallyDrop<sync::MutexGuard<'a, T>>,
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Wraps a held `inner` guard.
+    fn new(mutex: (LockId, Option<&'static str>), inner: sync::MutexGuard<'a, T>) -> Self {
+        Self {
+            mutex,
+            inner: ManuallyDrop::new(inner),
+        }
+    }
+
+    /// Records the release and returns the actual guard, which still needs to be dropped (or waited with).
+    fn into_inner(self) -> sync::MutexGuard<'a, T> {
+        let mut this = ManuallyDrop::new(self);
+        wait_for::released(this.mutex.0);
+        // SAFETY: Taken exactly once, as `this` isn't dropped.
+        unsafe { ManuallyDrop::take(&mut this.inner) }
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        wait_for::released(self.mutex.0);
+        // SAFETY: Dropped exactly once, here.
+        unsafe { ManuallyDrop::drop(&mut self.inner) }
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + Display> Display for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&**self, f)
+    }
+}
+
+/// A [`std::sync::RwLock`] that records who holds and waits on it.
+#[derive(Default)]
+pub struct RwLock<T: ?Sized> {
+    /// Identifies this lock in the wait-for graph.
+    id: LazyLockId,
+    /// Names this lock in [cycle reports](`WaitCycle`).
+    name: Option<&'static str>,
+    /// The actual lock.
+    inner: sync::RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    /// Creates a new unlocked reader-writer lock containing `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            id: LazyLockId::new(),
+            name: None,
+            inner: sync::RwLock::new(value),
+        }
+    }
+
+    /// Creates a new unlocked reader-writer lock containing `value`, which [cycle reports](`WaitCycle`) call `name`.
+    pub const fn named(name: &'static str, value: T) -> Self {
+        Self {
+            id: LazyLockId::new(),
+            name: Some(name),
+            inner: sync::RwLock::new(value),
+        }
+    }
+
+    /// Consumes this lock, returning the contained value.
+    ///
+    /// # Errors
+    ///
+    /// Iff the lock is poisoned, see [`std::sync::RwLock::into_inner`].
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// This lock's [`LockId`] in the wait-for graph.
+    #[must_use]
+    pub fn id(&self) -> LockId {
+        self.id.get()
+    }
+
+    /// This lock's name, if it was created [with one](`Self::named`).
+    #[must_use]
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// The [`LockId`] and name to record in the wait-for graph.
+    fn tracked(&self) -> (LockId, Option<&'static str>) {
+        (self.id(), self.name)
+    }
+
+    /// Acquires shared read access, blocking the current thread until it's able to do so.
+    ///
+    /// # Errors
+    ///
+    /// Iff the lock is poisoned, see [`std::sync::RwLock::read`].
+    #[track_caller]
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        let tracked = self.tracked();
+        map_result(
+            acquire(tracked, Access::Shared, Location::caller(), || {
+                self.inner.read()
+            }),
+            |inner| RwLockReadGuard {
+                id: tracked.0,
+                inner,
+            },
+        )
+    }
+
+    /// Attempts to acquire shared read access without blocking.
+    ///
+    /// # Errors
+    ///
+    /// Iff the lock is write-locked or poisoned, see [`std::sync::RwLock::try_read`].
+    #[track_caller]
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        let tracked = self.tracked();
+        map_try_result(
+            try_acquire(tracked, Access::Shared, Location::caller(), || {
+                self.inner.try_read()
+            }),
+            |inner| RwLockReadGuard {
+                id: tracked.0,
+                inner,
+            },
+        )
+    }
+
+    /// Acquires exclusive write access, blocking the current thread until it's able to do so.
+    ///
+    /// # Errors
+    ///
+    /// Iff the lock is poisoned, see [`std::sync::RwLock::write`].
+    #[track_caller]
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        let tracked = self.tracked();
+        map_result(
+            acquire(tracked, Access::Exclusive, Location::caller(), || {
+                self.inner.write()
+            }),
+            |inner| RwLockWriteGuard {
+                id: tracked.0,
+                inner,
+            },
+        )
+    }
+
+    /// Attempts to acquire exclusive write access without blocking.
+    ///
+    /// # Errors
+    ///
+    /// Iff the lock is locked or poisoned, see [`std::sync::RwLock::try_write`].
+    #[track_caller]
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        let tracked = self.tracked();
+        map_try_result(
+            try_acquire(tracked, Access::Exclusive, Location::caller(), || {
+                self.inner.try_write()
+            }),
+            |inner| RwLockWriteGuard {
+                id: tracked.0,
+                inner,
+            },
+        )
+    }
+
+    /// Whether the lock is poisoned.
+    #[must_use]
+    pub fn is_poisoned(&self) -> bool {
+        self.inner.is_poisoned()
+    }
+
+    /// Returns a mutable reference to the contained value, without locking.
+    ///
+    /// # Errors
+    ///
+    /// Iff the lock is poisoned, see [`std::sync::RwLock::get_mut`].
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        self.inner.get_mut()
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for RwLock<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RwLock")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .field("inner", &&self.inner)
+            .finish()
+    }
+}
+
+/// Shared read access to an [`RwLock`]. Dropping this releases it.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    /// The lock's ID.
+    id: LockId,
+    /// The actual guard.
+    inner: sync::RwLockReadGuard<'a, T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // The actual guard is dropped right after this.
+        wait_for::released(self.id);
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+/// Exclusive write access to an [`RwLock`]. Dropping this releases it.
+#[must_use = "if unused the RwLock will immediately unlock"]
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    /// The lock's ID.
+    id: LockId,
+    /// The actual guard.
+    inner: sync::RwLockWriteGuard<'a, T>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // The actual guard is dropped right after this.
+        wait_for::released(self.id);
+    }
+}
+
+impl<T: ?Sized + Debug> Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+/// A [`std::sync::Condvar`] for use with this module's [`Mutex`].
+///
+/// Waiting releases the mutex in the wait-for graph, and is itself recorded as a wait without holders,
+/// since it's unknown which thread would notify.
+#[derive(Debug, Default)]
+pub struct Condvar {
+    /// Identifies this condition variable in the wait-for graph.
+    id: LazyLockId,
+    /// Names this condition variable in [cycle reports](`WaitCycle`).
+    name: Option<&'static str>,
+    /// The actual condition variable.
+    inner: sync::Condvar,
+}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            id: LazyLockId::new(),
+            name: None,
+            inner: sync::Condvar::new(),
+        }
+    }
+
+    /// Creates a new condition variable, which [cycle reports](`WaitCycle`) call `name`.
+    #[must_use]
+    pub const fn named(name: &'static str) -> Self {
+        Self {
+            id: LazyLockId::new(),
+            name: Some(name),
+            inner: sync::Condvar::new(),
+        }
+    }
+
+    /// This condition variable's [`LockId`] in the wait-for graph.
+    #[must_use]
+    pub fn id(&self) -> LockId {
+        self.id.get()
+    }
+
+    /// This condition variable's name, if it was created [with one](`Self::named`).
+    #[must_use]
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// The [`LockId`] and name to record in the wait-for graph.
+    fn tracked(&self) -> (LockId, Option<&'static str>) {
+        (self.id(), self.name)
+    }
+
+    /// Blocks the current thread until notified, releasing `guard`'s mutex in the meantime.
+    ///
+    /// # Errors
+    ///
+    /// Iff the mutex is poisoned, see [`std::sync::Condvar::wait`].
+    #[track_caller]
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        let mutex = guard.mutex;
+        let location = Location::caller();
+        let result = self.waiting(location, || self.inner.wait(guard.into_inner()));
+        wait_for::acquired(mutex.0, mutex.1, Access::Exclusive, location);
+        map_result(result, |inner| MutexGuard::new(mutex, inner))
+    }
+
+    /// Blocks the current thread while `condition` holds, releasing `guard`'s mutex while waiting.
+    ///
+    /// # Errors
+    ///
+    /// Iff the mutex is poisoned, see [`std::sync::Condvar::wait_while`].
+    #[track_caller]
+    pub fn wait_while<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        condition: impl FnMut(&mut T) -> bool,
+    ) -> LockResult<MutexGuard<'a, T>> {
+        let mutex = guard.mutex;
+        let location = Location::caller();
+        let result = self.waiting(location, || {
+            self.inner.wait_while(guard.into_inner(), condition)
+        });
+        wait_for::acquired(mutex.0, mutex.1, Access::Exclusive, location);
+        map_result(result, |inner| MutexGuard::new(mutex, inner))
+    }
+
+    /// Like [`wait`](`Condvar::wait`), but gives up after `timeout`.
+    ///
+    /// # Errors
+    ///
+    /// Iff the mutex is poisoned, see [`std::sync::Condvar::wait_timeout`].
+    #[track_caller]
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, sync::WaitTimeoutResult)> {
+        let mutex = guard.mutex;
+        let location = Location::caller();
+        let result = self.waiting(location, || {
+            self.inner.wait_timeout(guard.into_inner(), timeout)
+        });
+        wait_for::acquired(mutex.0, mutex.1, Access::Exclusive, location);
+        map_result(result, |(inner, timeout)| {
+            (MutexGuard::new(mutex, inner), timeout)
+        })
+    }
+
+    /// Wakes up one blocked thread.
+    pub fn notify_one(&self) {
+        self.inner.notify_one();
+    }
+
+    /// Wakes up all blocked threads.
+    pub fn notify_all(&self) {
+        self.inner.notify_all();
+    }
+
+    /// Records waiting on this condition variable around `wait`, as called at `location`.
+    fn waiting<R>(&self, location: &'static Location<'static>, wait: impl FnOnce() -> R) -> R {
+        let (id, name) = self.tracked();
+        wait_for::wait(id, name, Access::Notification, location);
+        let result = wait();
+        wait_for::gave_up(id);
+        result
+    }
+}
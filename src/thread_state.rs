@@ -0,0 +1,92 @@
+//! Scheduler-level state of a worker thread.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+
+/// A sample of a worker thread's scheduler state.
+///
+/// This is only available on Linux, where it is read from `/proc/self/task/<tid>/{stat,schedstat,syscall,wchan}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadState {
+    /// The state letter from `stat`, e.g. `'R'` for running or `'S'` for sleeping.
+    pub state: char,
+    /// The number of the system call the thread is blocked in, if any and if readable.
+    ///
+    /// `syscall` can only be read with ptrace access to the thread,
+    /// which a process normally has for itself, but some sandboxes deny.
+    pub syscall: Option<i64>,
+    /// The arguments of [`syscall`](`ThreadState::syscall`), if readable.
+    ///
+    /// For `futex`, the first one is the address of the futex word being waited on.
+    pub syscall_args: Option<[u64; 6]>,
+    /// The kernel function the thread is waiting in, if known.
+    ///
+    /// This is often unavailable without elevated privileges.
+    pub wchan: Option<String>,
+    /// How much CPU time the thread consumed so far, if known.
+    pub cpu_time: Option<Duration>,
+}
+
+impl ThreadState {
+    /// Whether the thread is asleep in the kernel (`'S'` or `'D'`) and, as far as known, in a system call.
+    #[must_use]
+    pub fn is_blocked(&self) -> bool {
+        matches!(self.state, 'S' | 'D') && self.syscall.is_none_or(|syscall| syscall >= 0)
+    }
+
+    /// Whether `self` and `other` are both [blocked](`ThreadState::is_blocked`) in the same wait,
+    /// without any CPU time consumed in between.
+    ///
+    /// This compares the system call, its first argument (e.g. the futex address), [`wchan`](`ThreadState::wchan`) and [`cpu_time`](`ThreadState::cpu_time`).
+    #[must_use]
+    pub fn is_same_wait(&self, other: &Self) -> bool {
+        self.is_blocked()
+            && other.is_blocked()
+            && self.syscall == other.syscall
+            && self.syscall_args.map(|args| args[0]) == other.syscall_args.map(|args| args[0])
+            && self.wchan == other.wchan
+            && self.cpu_time == other.cpu_time
+    }
+
+    /// A readable name for [`syscall`](`ThreadState::syscall`), for a few common blocking system calls.
+    #[must_use]
+    pub fn syscall_name(&self) -> Option<&'static str> {
+        #[cfg(target_os = "linux")]
+        return self.syscall.and_then(crate::linux::syscall_name);
+        #[cfg(not(target_os = "linux"))]
+        return None;
+    }
+
+    /// A description of [`state`](`ThreadState::state`).
+    #[must_use]
+    pub fn state_name(&self) -> &'static str {
+        match self.state {
+            'R' => "running",
+            'S' => "sleeping",
+            'D' => "uninterruptible sleep",
+            'T' => "stopped",
+            't' => "tracing stop",
+            'Z' => "zombie",
+            'X' => "dead",
+            'I' => "idle",
+            _ => "unknown",
+        }
+    }
+}
+
+impl Display for ThreadState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.state, self.state_name())?;
+        match (self.syscall_name(), self.syscall) {
+            (Some(name), _) => write!(f, " in `{name}`")?,
+            (None, Some(syscall)) if syscall >= 0 => write!(f, " in syscall {syscall}")?,
+            _ => (),
+        }
+        if let Some(wchan) = &self.wchan {
+            write!(f, " at `{wchan}`")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,409 @@
+//! The global wait-for graph of [tracked locks](`crate::sync`).
+//!
+//! Holders are recorded only after acquiring and removed before releasing,
+//! and waiters before blocking, so every cycle found here is a real deadlock.
+
+use crate::lock_order::{self, LockClass};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    num::NonZeroU64,
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, Thread, ThreadId},
+};
+
+/// Identifies a [tracked lock](`crate::sync`) or [condition variable](`crate::sync::Condvar`).
+///
+/// IDs are assigned on first use, so they're unique within the process but not stable across runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LockId(NonZeroU64);
+
+impl Display for LockId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// A lazily assigned [`LockId`], so that tracked locks can be created in `const` contexts.
+pub struct LazyLockId(AtomicU64);
+
+impl LazyLockId {
+    /// Creates a [`LazyLockId`] that's assigned on first [`get`](`LazyLockId::get`).
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Returns the assigned [`LockId`], assigning one first if necessary.
+    pub fn get(&self) -> LockId {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+
+        let id = self.0.load(Ordering::Relaxed);
+        let id = if id == 0 {
+            let next = NEXT.fetch_add(1, Ordering::Relaxed);
+            match self
+                .0
+                .compare_exchange(0, next, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => next,
+                Err(id) => id,
+            }
+        } else {
+            id
+        };
+        LockId(NonZeroU64::new(id).expect("unreachable"))
+    }
+}
+
+impl Default for LazyLockId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for LazyLockId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.get(), f)
+    }
+}
+
+/// How a lock is (to be) held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Like a read lock, which other shared holders don't block.
+    Shared,
+    /// Like a mutex or write lock.
+    Exclusive,
+    /// Waiting on a condition variable, which has no holders to wait for.
+    Notification,
+}
+
+/// A thread holding or waiting on a lock.
+struct Entry {
+    /// The holding or waiting thread.
+    thread: Thread,
+    /// The lock.
+    lock: LockId,
+    /// The lock's name, if it has one.
+    name: Option<&'static str>,
+    /// How the lock is (to be) held.
+    access: Access,
+    /// Where the thread called to acquire it.
+    location: &'static Location<'static>,
+}
+
+impl Entry {
+    /// An entry for the current thread.
+    fn current(
+        lock: LockId,
+        name: Option<&'static str>,
+        access: Access,
+        location: &'static Location<'static>,
+    ) -> Self {
+        Self {
+            thread: thread::current(),
+            lock,
+            name,
+            access,
+            location,
+        }
+    }
+}
+
+/// The current holders of and waiters on tracked locks.
+struct Graph {
+    /// Which thread holds which lock.
+    holders: Vec<Entry>,
+    /// Which thread waits on which lock. Each thread waits on at most one.
+    waiters: Vec<Entry>,
+}
+
+/// The global wait-for graph.
+static GRAPH: Mutex<Graph> = Mutex::new(Graph {
+    holders: Vec::new(),
+    waiters: Vec::new(),
+});
+
+/// Whether any tracked lock was ever waited on.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Locks the global wait-for graph.
+fn graph() -> MutexGuard<'static, Graph> {
+    GRAPH.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Whether tracked locks are in use, so that looking for cycles is worthwhile.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Records that the current thread is about to block on `lock`, as called at `location`.
+///
+/// This also checks the [lock order](`lock_order`).
+pub fn wait(
+    lock: LockId,
+    name: Option<&'static str>,
+    access: Access,
+    location: &'static Location<'static>,
+) {
+    ACTIVE.store(true, Ordering::Relaxed);
+    let entry = Entry::current(lock, name, access, location);
+    let mut graph = graph();
+    let held: Vec<_> = if access == Access::Notification {
+        Vec::new()
+    } else {
+        graph
+            .holders
+            .iter()
+            .filter(|holder| holder.thread.id() == entry.thread.id())
+            .map(|holder| (LockClass::of(holder.lock, holder.name), holder.location))
+            .collect()
+    };
+    graph.waiters.push(entry);
+    drop(graph);
+    lock_order::acquiring(&held, LockClass::of(lock, name), location);
+}
+
+/// Records that the current thread acquired `lock`, as called at `location`, after [`wait`]ing for it or not.
+pub fn acquired(
+    lock: LockId,
+    name: Option<&'static str>,
+    access: Access,
+    location: &'static Location<'static>,
+) {
+    let entry = Entry::current(lock, name, access, location);
+    let mut graph = graph();
+    graph
+        .waiters
+        .retain(|waiter| waiter.thread.id() != entry.thread.id());
+    graph.holders.push(entry);
+}
+
+/// Records that the current thread stopped waiting on `lock` without acquiring it.
+pub fn gave_up(lock: LockId) {
+    let current = thread::current().id();
+    graph()
+        .waiters
+        .retain(|waiter| waiter.thread.id() != current || waiter.lock != lock);
+}
+
+/// Records that the current thread is about to release `lock`.
+pub fn released(lock: LockId) {
+    let current = thread::current().id();
+    let mut graph = graph();
+    if let Some(index) = graph
+        .holders
+        .iter()
+        .position(|holder| holder.thread.id() == current && holder.lock == lock)
+    {
+        graph.holders.swap_remove(index);
+    }
+}
+
+/// The lock `thread` currently waits on, with its name and where it called to acquire it.
+pub fn waiting_on(
+    thread: ThreadId,
+) -> Option<(LockId, Option<&'static str>, &'static Location<'static>)> {
+    graph()
+        .waiters
+        .iter()
+        .find(|waiter| waiter.thread.id() == thread)
+        .map(|waiter| (waiter.lock, waiter.name, waiter.location))
+}
+
+/// Finds a cycle of threads waiting on each other that `thread` is part of or waits on (transitively).
+pub fn find_cycle(thread: ThreadId) -> Option<WaitCycle> {
+    let graph = graph();
+    let mut path = Vec::new();
+    graph.search(thread, &mut path).map(|start| WaitCycle {
+        edges: path,
+        cycle_start: start,
+    })
+}
+
+impl Graph {
+    /// Depth-first search for a cycle, with the waits leading to `thread` in `path`.
+    ///
+    /// Returns the index in `path` at which the cycle begins, with `path` then leading up to and around it.
+    fn search(&self, thread: ThreadId, path: &mut Vec<WaitEdge>) -> Option<usize> {
+        if let Some(start) = path.iter().position(|edge| edge.thread.id() == thread) {
+            return Some(start);
+        }
+        let waiter = self
+            .waiters
+            .iter()
+            .find(|waiter| waiter.thread.id() == thread)?;
+        for holder in self
+            .holders
+            .iter()
+            .filter(|holder| holder.lock == waiter.lock && blocks(waiter.access, holder.access))
+        {
+            path.push(WaitEdge {
+                thread: waiter.thread.clone(),
+                lock: waiter.lock,
+                lock_name: waiter.name,
+                location: waiter.location,
+                holder_location: holder.location,
+            });
+            if let Some(start) = self.search(holder.thread.id(), path) {
+                return Some(start);
+            }
+            path.pop();
+        }
+        None
+    }
+}
+
+/// Whether a holder with `held` access blocks a waiter for `wanted` access.
+fn blocks(wanted: Access, held: Access) -> bool {
+    !matches!(
+        (wanted, held),
+        (Access::Notification, _) | (_, Access::Notification) | (Access::Shared, Access::Shared)
+    )
+}
+
+/// A thread waiting on a lock, as part of a [`WaitCycle`].
+#[derive(Debug, Clone)]
+pub struct WaitEdge {
+    /// The waiting thread.
+    thread: Thread,
+    /// The lock it waits on.
+    lock: LockId,
+    /// The lock's name, if it has one.
+    lock_name: Option<&'static str>,
+    /// Where the thread called to acquire the lock.
+    location: &'static Location<'static>,
+    /// Where the holding thread acquired the lock.
+    holder_location: &'static Location<'static>,
+}
+
+impl WaitEdge {
+    /// The waiting thread.
+    #[must_use]
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    /// The lock it waits on, which is held by the next edge's thread.
+    #[must_use]
+    pub fn lock(&self) -> LockId {
+        self.lock
+    }
+
+    /// The lock's name, if it was [given one](`crate::sync::Mutex::named`).
+    #[must_use]
+    pub fn lock_name(&self) -> Option<&'static str> {
+        self.lock_name
+    }
+
+    /// Where the waiting thread called to acquire the lock.
+    #[must_use]
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Where the holding thread acquired the lock.
+    #[must_use]
+    pub fn holder_location(&self) -> &'static Location<'static> {
+        self.holder_location
+    }
+}
+
+/// Formats a lock as `` `name` `` or, if it has none, as `lock #id`.
+pub struct LockLabel(pub LockId, pub Option<&'static str>);
+
+impl Display for LockLabel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(name) => write!(f, "`{name}`"),
+            None => write!(f, "lock {}", self.0),
+        }
+    }
+}
+
+/// Threads waiting on each other in a cycle, which proves a deadlock.
+///
+/// The first edges may lead from the thread in question into the cycle, if it isn't part of it itself.
+///
+/// This displays as a report like
+/// "thread 'a' holds `accounts` (src/bank.rs:10:5) and waits for `ledger` (src/bank.rs:11:5); thread 'b' holds `ledger` (src/bank.rs:20:5) and waits for `accounts` (src/bank.rs:21:5)",
+/// with the locations where each lock was acquired and is being waited for.
+/// Locks without a [name](`crate::sync::Mutex::named`) are shown by [`LockId`].
+#[derive(Debug, Clone)]
+pub struct WaitCycle {
+    /// Each thread waits on a lock held by the next, with the last one waiting on the one at `cycle_start`.
+    edges: Vec<WaitEdge>,
+    /// Where the cycle proper begins in `edges`.
+    cycle_start: usize,
+}
+
+impl WaitCycle {
+    /// The threads that wait, each on a lock held by the next one's thread.
+    ///
+    /// The last one waits on a lock held by the thread of [`edges()[cycle_start()]`](`WaitCycle::cycle_start`).
+    #[must_use]
+    pub fn edges(&self) -> &[WaitEdge] {
+        &self.edges
+    }
+
+    /// Where the cycle proper begins in [`edges`](`WaitCycle::edges`).
+    ///
+    /// This is `0` if the thread in question is itself part of the cycle.
+    #[must_use]
+    pub fn cycle_start(&self) -> usize {
+        self.cycle_start
+    }
+}
+
+impl WaitCycle {
+    /// The index of the edge whose thread holds the lock that `edges[index]` waits on.
+    fn holder(&self, index: usize) -> usize {
+        if index + 1 < self.edges.len() {
+            index + 1
+        } else {
+            self.cycle_start
+        }
+    }
+}
+
+impl Display for WaitCycle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (index, edge) in self.edges.iter().enumerate() {
+            if index > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "thread '{}' ", edge.thread.name().unwrap_or("<unnamed>"))?;
+            let mut held = self
+                .edges
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| self.holder(other) == index)
+                .map(|(_, held)| held)
+                .peekable();
+            if held.peek().is_some() {
+                write!(f, "holds ")?;
+                for (i, held) in held.enumerate() {
+                    if i > 0 {
+                        write!(f, " and ")?;
+                    }
+                    write!(
+                        f,
+                        "{} ({})",
+                        LockLabel(held.lock, held.lock_name),
+                        held.holder_location
+                    )?;
+                }
+                write!(f, " and ")?;
+            }
+            write!(
+                f,
+                "waits for {} ({})",
+                LockLabel(edge.lock, edge.lock_name),
+                edge.location
+            )?;
+        }
+        Ok(())
+    }
+}
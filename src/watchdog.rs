@@ -0,0 +1,78 @@
+//! Timeouts for statements that run on the calling thread.
+
+use crate::Config;
+use std::{
+    io::{stderr, Write},
+    process,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+/// Runs `stmt` on the calling thread, while a watchdog thread aborts the process if it doesn't complete in time.
+///
+/// This is for statements that can't be moved to another thread, as they aren't [`Send`].
+/// Since a blocked calling thread can't be interrupted, there's no way to recover from a timeout here.
+/// Instead, `report` is written to standard error (bypassing test output capture) before the process aborts.
+///
+/// Panics from `stmt` propagate normally.
+///
+/// # Panics
+///
+/// Iff `stmt` panics, or if the watchdog thread can't be spawned.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::{rc::Rc, time::Duration};
+/// use assert_deadlock::run_with_watchdog;
+///
+/// let rc = Rc::new(1);
+/// let value = run_with_watchdog(|| *rc + 1, Duration::from_secs(1), "Still blocked!");
+/// assert_eq!(value, 2);
+/// ```
+pub fn run_with_watchdog<T>(
+    stmt: impl FnOnce() -> T,
+    config: impl Into<Config>,
+    report: impl Into<String>,
+) -> T {
+    let config = config.into();
+    let report = report.into();
+    let _watchdog = Watchdog::arm(&config, report);
+    stmt()
+}
+
+/// Aborts the process unless dropped in time.
+struct Watchdog {
+    /// Set to `true` and notified when disarmed.
+    disarmed: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Watchdog {
+    /// Spawns a watchdog thread that aborts the process after `config.duration()`, writing `report` to standard error.
+    fn arm(config: &Config, report: String) -> Self {
+        let disarmed = Arc::new((Mutex::new(false), Condvar::new()));
+        let duration = config.duration();
+        thread::spawn({
+            let disarmed = Arc::clone(&disarmed);
+            move || {
+                let (disarmed, changed) = &*disarmed;
+                let (disarmed, _) = changed
+                    .wait_timeout_while(disarmed.lock().unwrap(), duration, |disarmed| !*disarmed)
+                    .unwrap();
+                if !*disarmed {
+                    let _ = writeln!(stderr(), "{report}");
+                    process::abort();
+                }
+            }
+        });
+        Self { disarmed }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (disarmed, changed) = &*self.disarmed;
+        *disarmed.lock().unwrap() = true;
+        changed.notify_all();
+    }
+}
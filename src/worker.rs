@@ -0,0 +1,253 @@
+//! The worker thread that runs the statement under test, and the slot it reports back through.
+
+#[cfg(target_os = "linux")]
+use crate::linux::{self, Tid};
+use crate::{
+    leaks::{self, Leak},
+    output::Sink,
+    panics::{self, WorkerPanic},
+    Config, ThreadState,
+};
+use std::{
+    backtrace::Backtrace,
+    mem::transmute,
+    panic::Location,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle, Thread},
+    time::{Duration, Instant},
+};
+
+/// A statement ready to be run on a [`Worker`].
+pub type Statement<T> = Box<dyn FnOnce() -> T + Send + 'static>;
+
+/// Erases the lifetime of a borrowing statement so it can be moved onto a [`Worker`].
+///
+/// # Safety
+///
+/// The same type, but the caller must make sure that anything `stmt` borrows outlives the worker thread.
+#[must_use]
+pub unsafe fn extend<'a, T>(stmt: Box<dyn FnOnce() -> T + Send + 'a>) -> Statement<T> {
+    transmute(stmt)
+}
+
+/// Runs a statement on a detached thread and notifies the spawning thread once it starts and completes.
+///
+/// Dropping this while the statement still runs [registers](`leaks::register`) the thread as leaked.
+pub struct Worker<T> {
+    /// The result slot shared with the worker thread.
+    shared: Arc<Shared<T>>,
+    /// The worker thread.
+    thread: Thread,
+    /// The worker thread's handle, until joined or leaked.
+    handle: Option<JoinHandle<()>>,
+    /// Where the check that spawned this worker was called.
+    location: &'static Location<'static>,
+    /// The statement's [`worker_output`](`crate::worker_output`).
+    output: Sink,
+}
+
+/// State shared between a [`Worker`] and its thread.
+struct Shared<T> {
+    /// The worker's progress.
+    state: Mutex<State<T>>,
+    /// Notified whenever `state` changes.
+    changed: Condvar,
+}
+
+/// A [`Worker`]'s progress.
+struct State<T> {
+    /// When the statement started running, if it did.
+    started: Option<Instant>,
+    /// The worker's kernel thread ID, once it started.
+    #[cfg(target_os = "linux")]
+    tid: Option<Tid>,
+    /// [`None`] while the statement runs.
+    result: Option<Result<T, WorkerPanic>>,
+    /// Whether the statement completed, even if `result` was taken since.
+    completed: bool,
+}
+
+impl<T> Shared<T> {
+    /// Locks the worker's state.
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        // Nothing panics while holding this lock.
+        self.state.lock().expect("unreachable")
+    }
+
+    /// Waits for up to `timeout` while `condition` holds.
+    fn wait_while(
+        &self,
+        timeout: Duration,
+        condition: impl FnMut(&mut State<T>) -> bool,
+    ) -> MutexGuard<'_, State<T>> {
+        self.changed
+            .wait_timeout_while(self.lock(), timeout, condition)
+            .expect("unreachable")
+            .0
+    }
+}
+
+impl<T: Send + 'static> Worker<T> {
+    /// Spawns a thread running `stmt`, named and sized as configured.
+    ///
+    /// The thread is named after the caller's location by default.
+    ///
+    /// # Panics
+    ///
+    /// Iff the thread can't be spawned, or the [leaked threads limit](`leaks::set_leaked_threads_limit`) is reached.
+    #[must_use]
+    #[track_caller]
+    pub fn spawn(stmt: Statement<T>, config: &Config) -> Self {
+        leaks::check_limit();
+        let location = Location::caller();
+        let mut builder = thread::Builder::new().name(config.thread_name().map_or_else(
+            || format!("assert_deadlock@{}:{}", location.file(), location.line()),
+            ToString::to_string,
+        ));
+        if let Some(stack_size) = config.stack_size() {
+            builder = builder.stack_size(stack_size);
+        }
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                started: None,
+                #[cfg(target_os = "linux")]
+                tid: None,
+                result: None,
+                completed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let output = Sink::default();
+        let handle = builder
+            .spawn({
+                let shared = Arc::clone(&shared);
+                let output = output.clone();
+                move || {
+                    output.install();
+                    {
+                        let mut state = shared.lock();
+                        #[cfg(target_os = "linux")]
+                        {
+                            state.tid = Some(linux::gettid());
+                        }
+                        state.started = Some(Instant::now());
+                    }
+                    shared.changed.notify_all();
+                    // Like with `thread::spawn`, the panic is only observed as payload.
+                    let result = panics::catch(stmt);
+                    {
+                        let mut state = shared.lock();
+                        state.result = Some(result);
+                        state.completed = true;
+                    }
+                    shared.changed.notify_all();
+                }
+            })
+            .expect("assert_deadlock: Could not spawn worker thread");
+        Self {
+            shared,
+            thread: handle.thread().clone(),
+            handle: Some(handle),
+            location,
+            output,
+        }
+    }
+}
+
+impl<T> Worker<T> {
+    /// The worker thread.
+    #[must_use]
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    /// Where the statement's [`worker_output`](`crate::worker_output`) goes.
+    #[must_use]
+    pub fn output(&self) -> &Sink {
+        &self.output
+    }
+
+    /// Samples the worker thread's [`ThreadState`].
+    ///
+    /// Returns [`None`] if that's not supported on this platform, the thread didn't start yet or already exited.
+    #[must_use]
+    pub fn sample(&self) -> Option<ThreadState> {
+        #[cfg(target_os = "linux")]
+        return linux::sample(self.shared.lock().tid?).ok();
+        #[cfg(not(target_os = "linux"))]
+        return None;
+    }
+
+    /// Captures the worker thread's current stack, while the statement is still running and blocked.
+    ///
+    /// Returns [`None`] if that's not supported on this platform, or the statement isn't running (anymore),
+    /// or isn't [blocked](`ThreadState::is_blocked`) right before.
+    /// A running thread may be inside the allocator or capturing a backtrace itself,
+    /// where the signal handler could corrupt the heap or hang.
+    /// A blocked thread may be too, if it sleeps on a lock there, so this only makes that less likely.
+    #[must_use]
+    pub fn backtrace(&self) -> Option<Backtrace> {
+        // Holding the lock keeps the thread from exiting in the meantime.
+        let state = self.shared.lock();
+        if state.started.is_none() || state.completed {
+            return None;
+        }
+        #[cfg(target_os = "linux")]
+        return state
+            .tid
+            .filter(|&tid| linux::sample(tid).is_ok_and(|state| state.is_blocked()))
+            .and_then(linux::backtrace);
+        #[cfg(not(target_os = "linux"))]
+        return None;
+    }
+
+    /// Waits for the statement to start running.
+    ///
+    /// Returns when it started, or [`None`] iff the thread wasn't scheduled within `timeout`.
+    #[must_use]
+    pub fn start(&self, timeout: Duration) -> Option<Instant> {
+        self.shared
+            .wait_while(timeout, |state| state.started.is_none())
+            .started
+    }
+
+    /// Joins the worker thread after the statement completed.
+    ///
+    /// This only waits for the thread's brief cleanup after [`wait_until`](`Worker::wait_until`) returned a result.
+    pub fn join(mut self) {
+        if let Some(handle) = self.handle.take() {
+            // The statement's panics are caught, so the thread itself doesn't panic.
+            let _ = handle.join();
+        }
+    }
+
+    /// Waits for the statement to complete.
+    ///
+    /// Returns [`None`] iff it's still running at `deadline`.
+    #[must_use]
+    pub fn wait_until(&self, deadline: Instant) -> Option<Result<T, WorkerPanic>> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.shared
+            .wait_while(timeout, |state| state.result.is_none())
+            .result
+            .take()
+    }
+}
+
+impl<T> Drop for Worker<T> {
+    fn drop(&mut self) {
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        let state = self.shared.lock();
+        if !state.completed {
+            leaks::register(Leak {
+                location: self.location,
+                started: state.started.unwrap_or_else(Instant::now),
+                #[cfg(target_os = "linux")]
+                tid: state.tid,
+                handle,
+            });
+        }
+    }
+}